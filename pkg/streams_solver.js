@@ -1,5 +1,20 @@
 let wasm;
 
+function addToExternrefTable0(obj) {
+    const idx = wasm.__externref_table_alloc();
+    wasm.__wbindgen_export_2.set(idx, obj);
+    return idx;
+}
+
+function handleError(f, args) {
+    try {
+        return f.apply(this, args);
+    } catch (e) {
+        const idx = addToExternrefTable0(e);
+        wasm.__wbindgen_exn_store(idx);
+    }
+}
+
 const cachedTextDecoder = (typeof TextDecoder !== 'undefined' ? new TextDecoder('utf-8', { ignoreBOM: true, fatal: true }) : { decode: () => { throw Error('TextDecoder not available') } } );
 
 if (typeof TextDecoder !== 'undefined') { cachedTextDecoder.decode(); };
@@ -18,6 +33,19 @@ function getStringFromWasm0(ptr, len) {
     return cachedTextDecoder.decode(getUint8ArrayMemory0().subarray(ptr, ptr + len));
 }
 
+function isLikeNone(x) {
+    return x === undefined || x === null;
+}
+
+let cachedDataViewMemory0 = null;
+
+function getDataViewMemory0() {
+    if (cachedDataViewMemory0 === null || cachedDataViewMemory0.buffer.detached === true || (cachedDataViewMemory0.buffer.detached === undefined && cachedDataViewMemory0.buffer !== wasm.memory.buffer)) {
+        cachedDataViewMemory0 = new DataView(wasm.memory.buffer);
+    }
+    return cachedDataViewMemory0;
+}
+
 let WASM_VECTOR_LEN = 0;
 
 const cachedTextEncoder = (typeof TextEncoder !== 'undefined' ? new TextEncoder('utf-8') : { encode: () => { throw Error('TextEncoder not available') } } );
@@ -73,31 +101,322 @@ function passStringToWasm0(arg, malloc, realloc) {
     WASM_VECTOR_LEN = offset;
     return ptr;
 }
+
+function takeFromExternrefTable0(idx) {
+    const value = wasm.__wbindgen_export_2.get(idx);
+    wasm.__externref_table_dealloc(idx);
+    return value;
+}
 /**
+ * `card` を置く各マスについて `{pos, dist}` の配列
  * @param {string} board
+ * @param {number} card
  * @param {number} sims
- * @returns {number}
+ * @param {string | null} [deck]
+ * @returns {Array<any>}
  */
-export function expected_value_current_board(board, sims) {
+export function score_histograms_after_card(board, card, sims, deck) {
     const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
     const len0 = WASM_VECTOR_LEN;
-    const ret = wasm.expected_value_current_board(ptr0, len0, sims);
-    return ret;
+    var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len1 = WASM_VECTOR_LEN;
+    const ret = wasm.score_histograms_after_card(ptr0, len0, card, sims, ptr1, len1);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return takeFromExternrefTable0(ret[0]);
 }
 
 /**
+ * マスごとに評価し、1 マス終わるたびに `progress(done, total, ev)` を呼ぶ。中断されたら残りのマスは 0
  * @param {string} board
  * @param {number} card
  * @param {number} sims
+ * @param {number | null} [budget_ms]
+ * @param {string | null} [deck]
+ * @param {string | null} [algo]
+ * @param {Function | null} [progress]
+ * @param {object | null} [abort]
  * @returns {Float64Array}
  */
-export function expected_values_after_card(board, card, sims) {
+export function expected_values_after_card(board, card, sims, budget_ms, deck, algo, progress, abort) {
+    const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    const len0 = WASM_VECTOR_LEN;
+    var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len1 = WASM_VECTOR_LEN;
+    var ptr2 = isLikeNone(algo) ? 0 : passStringToWasm0(algo, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len2 = WASM_VECTOR_LEN;
+    const ret = wasm.expected_values_after_card(ptr0, len0, card, sims, !isLikeNone(budget_ms), isLikeNone(budget_ms) ? 0 : budget_ms, ptr1, len1, ptr2, len2, isLikeNone(progress) ? 0 : addToExternrefTable0(progress), isLikeNone(abort) ? 0 : addToExternrefTable0(abort));
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return takeFromExternrefTable0(ret[0]);
+}
+
+/**
+ * `progress(done, total, estimate)` は任意の途中経過コールバック (`false` を返すと中断)、
+ * `abort` は任意の中断ハンドル
+ * @param {string} board
+ * @param {number} sims
+ * @param {number | null} [budget_ms]
+ * @param {string | null} [deck]
+ * @param {string | null} [algo]
+ * @param {Function | null} [progress]
+ * @param {object | null} [abort]
+ * @returns {number}
+ */
+export function expected_value_current_board(board, sims, budget_ms, deck, algo, progress, abort) {
+    const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    const len0 = WASM_VECTOR_LEN;
+    var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len1 = WASM_VECTOR_LEN;
+    var ptr2 = isLikeNone(algo) ? 0 : passStringToWasm0(algo, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len2 = WASM_VECTOR_LEN;
+    const ret = wasm.expected_value_current_board(ptr0, len0, sims, !isLikeNone(budget_ms), isLikeNone(budget_ms) ? 0 : budget_ms, ptr1, len1, ptr2, len2, isLikeNone(progress) ? 0 : addToExternrefTable0(progress), isLikeNone(abort) ? 0 : addToExternrefTable0(abort));
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return ret[0];
+}
+
+/**
+ * 札 × マスの評価表 `{values, stderr, best, samples}`。values / stderr は行優先
+ * (`(card - 1) * 20 + pos`、Joker = 31) の Float64Array で、山札に無い札と埋まったマスは NaN。
+ * best は札ごとの最善マス (無ければ -1) の Int32Array
+ * @param {string} board
+ * @param {number} sims
+ * @param {number | null} [budget_ms]
+ * @param {string | null} [deck]
+ * @returns {any}
+ */
+export function evMatrix(board, sims, budget_ms, deck) {
     const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
     const len0 = WASM_VECTOR_LEN;
-    const ret = wasm.expected_values_after_card(ptr0, len0, card, sims);
+    var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len1 = WASM_VECTOR_LEN;
+    const ret = wasm.evMatrix(ptr0, len0, sims, !isLikeNone(budget_ms), isLikeNone(budget_ms) ? 0 : budget_ms, ptr1, len1);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return takeFromExternrefTable0(ret[0]);
+}
+
+/**
+ * Worker 用: `recommend` のシャード `shard` / `shards` を `{pos, n, mean, m2}` の配列で返す。
+ * 全 Worker に同じ `seed` を渡し、結果を `merge_recommend` に集める
+ * @param {string} board
+ * @param {number} card
+ * @param {number} sims
+ * @param {number} seed
+ * @param {number} shard
+ * @param {number} shards
+ * @param {number | null} [budget_ms]
+ * @param {string | null} [deck]
+ * @param {string | null} [algo]
+ * @returns {Array<any>}
+ */
+export function recommendShard(board, card, sims, seed, shard, shards, budget_ms, deck, algo) {
+    const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    const len0 = WASM_VECTOR_LEN;
+    var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len1 = WASM_VECTOR_LEN;
+    var ptr2 = isLikeNone(algo) ? 0 : passStringToWasm0(algo, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len2 = WASM_VECTOR_LEN;
+    const ret = wasm.recommendShard(ptr0, len0, card, sims, seed, shard, shards, !isLikeNone(budget_ms), isLikeNone(budget_ms) ? 0 : budget_ms, ptr1, len1, ptr2, len2);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return takeFromExternrefTable0(ret[0]);
+}
+
+/**
+ * 候補マスを平均の降順で `{pos, mean, stderr, samples, gap}` の配列として返す。
+ * `progress(done, total, best)` はバッチごとに呼ばれる
+ * @param {string} board
+ * @param {number} card
+ * @param {number} sims
+ * @param {number | null} [budget_ms]
+ * @param {string | null} [deck]
+ * @param {string | null} [algo]
+ * @param {Function | null} [progress]
+ * @param {object | null} [abort]
+ * @returns {Array<any>}
+ */
+export function recommend(board, card, sims, budget_ms, deck, algo, progress, abort) {
+    const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    const len0 = WASM_VECTOR_LEN;
+    var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len1 = WASM_VECTOR_LEN;
+    var ptr2 = isLikeNone(algo) ? 0 : passStringToWasm0(algo, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len2 = WASM_VECTOR_LEN;
+    const ret = wasm.recommend(ptr0, len0, card, sims, !isLikeNone(budget_ms), isLikeNone(budget_ms) ? 0 : budget_ms, ptr1, len1, ptr2, len2, isLikeNone(progress) ? 0 : addToExternrefTable0(progress), isLikeNone(abort) ? 0 : addToExternrefTable0(abort));
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return takeFromExternrefTable0(ret[0]);
+}
+
+/**
+ * 現盤面の最終得点分布 `{counts, min, samples, mean, p10, p50, p90}` (counts[i] は得点 min + i)
+ * @param {string} board
+ * @param {number} sims
+ * @param {string | null} [deck]
+ * @returns {any}
+ */
+export function score_histogram(board, sims, deck) {
+    const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    const len0 = WASM_VECTOR_LEN;
+    var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+    var len1 = WASM_VECTOR_LEN;
+    const ret = wasm.score_histogram(ptr0, len0, sims, ptr1, len1);
+    if (ret[2]) {
+        throw takeFromExternrefTable0(ret[1]);
+    }
+    return takeFromExternrefTable0(ret[0]);
+}
+
+/**
+ * `recommendShard` の結果の配列 (Worker ごと) を合流し、`recommend` と同じ形で返す
+ * @param {Array<any>} parts
+ * @returns {Array<any>}
+ */
+export function mergeRecommend(parts) {
+    const ret = wasm.mergeRecommend(parts);
     return ret;
 }
 
+const SolverFinalization = (typeof FinalizationRegistry === 'undefined')
+    ? { register: () => {}, unregister: () => {} }
+    : new FinalizationRegistry(ptr => wasm.__wbg_solver_free(ptr >>> 0, 1));
+/**
+ * 1 局を通して持つソルバ。手番をまたいで推定値と置換表を使い回す
+ * ```js
+ * const s = new Solver(board, 20);        // (board, sims, deck?, algo?)
+ * s.valuesAfterCard(card, 200);          // [{pos, mean, stderr, samples, gap}]  呼ぶほど精度が上がる
+ * s.place(card, pos); s.undo();
+ * ```
+ */
+export class Solver {
+
+    __destroy_into_raw() {
+        const ptr = this.__wbg_ptr;
+        this.__wbg_ptr = 0;
+        SolverFinalization.unregister(this);
+        return ptr;
+    }
+
+    free() {
+        const ptr = this.__destroy_into_raw();
+        wasm.__wbg_solver_free(ptr, 0);
+    }
+    /**
+     * 現局面の推定値 `{mean, stderr, samples, exact}`
+     * @param {number | null} [budget_ms]
+     * @returns {any}
+     */
+    expectedValue(budget_ms) {
+        const ret = wasm.solver_expectedValue(this.__wbg_ptr, !isLikeNone(budget_ms), isLikeNone(budget_ms) ? 0 : budget_ms);
+        return ret;
+    }
+    /**
+     * `recommend` と同じ形の配列
+     * @param {number} card
+     * @param {number | null} [budget_ms]
+     * @returns {Array<any>}
+     */
+    valuesAfterCard(card, budget_ms) {
+        const ret = wasm.solver_valuesAfterCard(this.__wbg_ptr, card, !isLikeNone(budget_ms), isLikeNone(budget_ms) ? 0 : budget_ms);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        return takeFromExternrefTable0(ret[0]);
+    }
+    /**
+     * @param {string} board
+     * @param {number} sims
+     * @param {string | null} [deck]
+     * @param {string | null} [algo]
+     */
+    constructor(board, sims, deck, algo) {
+        const ptr0 = passStringToWasm0(board, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+        const len0 = WASM_VECTOR_LEN;
+        var ptr1 = isLikeNone(deck) ? 0 : passStringToWasm0(deck, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+        var len1 = WASM_VECTOR_LEN;
+        var ptr2 = isLikeNone(algo) ? 0 : passStringToWasm0(algo, wasm.__wbindgen_malloc, wasm.__wbindgen_realloc);
+        var len2 = WASM_VECTOR_LEN;
+        const ret = wasm.solver_new(ptr0, len0, sims, ptr1, len1, ptr2, len2);
+        if (ret[2]) {
+            throw takeFromExternrefTable0(ret[1]);
+        }
+        this.__wbg_ptr = ret[0] >>> 0;
+        SolverFinalization.register(this, this.__wbg_ptr, this);
+        return this;
+    }
+    /**
+     * @returns {number | undefined}
+     */
+    get card() {
+        const ret = wasm.solver_card(this.__wbg_ptr);
+        return ret === 0xFFFFFF ? undefined : ret;
+    }
+    /**
+     * rollout を `n_rollouts` 本足して、マスごとの推定を `recommend` と同じ形で返す
+     * (95% 信頼幅は mean ± 1.96·stderr)。requestAnimationFrame から少しずつ呼ぶ:
+     * ```js
+     * s.setCard(card);
+     * const tick = () => { draw(s.step(300)); if (s.card !== undefined) requestAnimationFrame(tick); };
+     * ```
+     * @param {number} n_rollouts
+     * @returns {Array<any>}
+     */
+    step(n_rollouts) {
+        const ret = wasm.solver_step(this.__wbg_ptr, n_rollouts);
+        return ret;
+    }
+    /**
+     * 取り消した手 `{card, pos}` (手順が空なら undefined)
+     * @returns {any}
+     */
+    undo() {
+        const ret = wasm.solver_undo(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * キャッシュを捨てる (局面と手順はそのまま)
+     */
+    clear() {
+        wasm.solver_clear(this.__wbg_ptr);
+    }
+    /**
+     * @param {number} card
+     * @param {number} pos
+     */
+    place(card, pos) {
+        const ret = wasm.solver_place(this.__wbg_ptr, card, pos);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+    /**
+     * `{positions, samples, reused, ttHits}`
+     * @returns {any}
+     */
+    stats() {
+        const ret = wasm.solver_stats(this.__wbg_ptr);
+        return ret;
+    }
+    /**
+     * `step` で評価する札。省略で解除
+     * @param {number | null} [card]
+     */
+    setCard(card) {
+        const ret = wasm.solver_setCard(this.__wbg_ptr, isLikeNone(card) ? 0xFFFFFF : card);
+        if (ret[1]) {
+            throw takeFromExternrefTable0(ret[0]);
+        }
+    }
+}
+
 async function __wbg_load(module, imports) {
     if (typeof Response === 'function' && module instanceof Response) {
         if (typeof WebAssembly.instantiateStreaming === 'function') {
@@ -136,20 +455,81 @@ function __wbg_get_imports() {
         const ret = arg0.buffer;
         return ret;
     };
+    imports.wbg.__wbg_call_b8adc8b1d0a0d8eb = function() { return handleError(function (arg0, arg1, arg2, arg3, arg4) {
+        const ret = arg0.call(arg1, arg2, arg3, arg4);
+        return ret;
+    }, arguments) };
+    imports.wbg.__wbg_from_2a5d3e218e67aa85 = function(arg0) {
+        const ret = Array.from(arg0);
+        return ret;
+    };
+    imports.wbg.__wbg_get_67b2ba62fc30de12 = function() { return handleError(function (arg0, arg1) {
+        const ret = Reflect.get(arg0, arg1);
+        return ret;
+    }, arguments) };
+    imports.wbg.__wbg_get_b9b93047fe3cf45b = function(arg0, arg1) {
+        const ret = arg0[arg1 >>> 0];
+        return ret;
+    };
+    imports.wbg.__wbg_length_e2d2a49132c1b256 = function(arg0) {
+        const ret = arg0.length;
+        return ret;
+    };
+    imports.wbg.__wbg_new_405e22f390576ce2 = function() {
+        const ret = new Object();
+        return ret;
+    };
     imports.wbg.__wbg_new_78c8a92080461d08 = function(arg0) {
         const ret = new Float64Array(arg0);
         return ret;
     };
+    imports.wbg.__wbg_new_78feb108b6472713 = function() {
+        const ret = new Array();
+        return ret;
+    };
+    imports.wbg.__wbg_new_c68d7209be747379 = function(arg0, arg1) {
+        const ret = new Error(getStringFromWasm0(arg0, arg1));
+        return ret;
+    };
+    imports.wbg.__wbg_new_e3b321dcfef89fc7 = function(arg0) {
+        const ret = new Uint32Array(arg0);
+        return ret;
+    };
+    imports.wbg.__wbg_new_e9a4a67dbababe57 = function(arg0) {
+        const ret = new Int32Array(arg0);
+        return ret;
+    };
     imports.wbg.__wbg_newwithbyteoffsetandlength_93c8e0c1a479fa1a = function(arg0, arg1, arg2) {
         const ret = new Float64Array(arg0, arg1 >>> 0, arg2 >>> 0);
         return ret;
     };
+    imports.wbg.__wbg_newwithbyteoffsetandlength_999332a180064b59 = function(arg0, arg1, arg2) {
+        const ret = new Int32Array(arg0, arg1 >>> 0, arg2 >>> 0);
+        return ret;
+    };
+    imports.wbg.__wbg_newwithbyteoffsetandlength_f1dead44d1fc7212 = function(arg0, arg1, arg2) {
+        const ret = new Uint32Array(arg0, arg1 >>> 0, arg2 >>> 0);
+        return ret;
+    };
     imports.wbg.__wbg_now_807e54c39636c349 = function() {
         const ret = Date.now();
         return ret;
     };
+    imports.wbg.__wbg_push_737cfc8c1432c2c6 = function(arg0, arg1) {
+        const ret = arg0.push(arg1);
+        return ret;
+    };
+    imports.wbg.__wbg_set_bb8cecf6a62b9f46 = function() { return handleError(function (arg0, arg1, arg2) {
+        const ret = Reflect.set(arg0, arg1, arg2);
+        return ret;
+    }, arguments) };
+    imports.wbg.__wbindgen_boolean_get = function(arg0) {
+        const v = arg0;
+        const ret = typeof(v) === 'boolean' ? (v ? 1 : 0) : 2;
+        return ret;
+    };
     imports.wbg.__wbindgen_init_externref_table = function() {
-        const table = wasm.__wbindgen_export_0;
+        const table = wasm.__wbindgen_export_2;
         const offset = table.grow(4);
         table.set(0, undefined);
         table.set(offset + 0, undefined);
@@ -158,10 +538,28 @@ function __wbg_get_imports() {
         table.set(offset + 3, false);
         ;
     };
+    imports.wbg.__wbindgen_is_falsy = function(arg0) {
+        const ret = !arg0;
+        return ret;
+    };
     imports.wbg.__wbindgen_memory = function() {
         const ret = wasm.memory;
         return ret;
     };
+    imports.wbg.__wbindgen_number_get = function(arg0, arg1) {
+        const obj = arg1;
+        const ret = typeof(obj) === 'number' ? obj : undefined;
+        getDataViewMemory0().setFloat64(arg0 + 8 * 1, isLikeNone(ret) ? 0 : ret, true);
+        getDataViewMemory0().setInt32(arg0 + 4 * 0, !isLikeNone(ret), true);
+    };
+    imports.wbg.__wbindgen_number_new = function(arg0) {
+        const ret = arg0;
+        return ret;
+    };
+    imports.wbg.__wbindgen_string_new = function(arg0, arg1) {
+        const ret = getStringFromWasm0(arg0, arg1);
+        return ret;
+    };
     imports.wbg.__wbindgen_throw = function(arg0, arg1) {
         throw new Error(getStringFromWasm0(arg0, arg1));
     };
@@ -176,6 +574,7 @@ function __wbg_init_memory(imports, memory) {
 function __wbg_finalize_init(instance, module) {
     wasm = instance.exports;
     __wbg_init.__wbindgen_wasm_module = module;
+    cachedDataViewMemory0 = null;
     cachedUint8ArrayMemory0 = null;
 
 
@@ -0,0 +1,56 @@
+// Worker プール: Wasm の重い計算をメインスレッドの外で回す
+//  * call(fn, ...args)  … 空いている Worker で export 関数を 1 回呼ぶ
+//  * recommend(...)     … recommendShard を Worker 数に分けて投げ、mergeRecommend で合流
+import { mergeRecommend } from "./pkg/streams_solver.js";
+
+// recommend のバッチ数 (= RECOMMEND_BATCHES)。これより多く分けても遊ぶだけ
+const MAX_SHARDS = 8;
+
+export class SolverPool {
+  constructor(size = Math.min(navigator.hardwareConcurrency || 4, MAX_SHARDS)) {
+    this.workers = Array.from({ length: size }, () => new Worker(new URL("./worker.js", import.meta.url), { type: "module" }));
+    this.idle = [...this.workers];
+    this.queue = [];
+    this.pending = new Map();
+    this.nextId = 0;
+    for (const w of this.workers) {
+      w.onmessage = ({ data: { id, ok, err } }) => {
+        const { resolve, reject } = this.pending.get(id);
+        this.pending.delete(id);
+        this.idle.push(w);
+        this.pump();
+        if (err) reject(Object.assign(new Error(err.message), err)); else resolve(ok);
+      };
+    }
+  }
+
+  get size() { return this.workers.length; }
+
+  call(fn, ...args) {
+    return new Promise((resolve, reject) => {
+      this.queue.push({ fn, args, resolve, reject });
+      this.pump();
+    });
+  }
+
+  pump() {
+    while (this.idle.length && this.queue.length) {
+      const w = this.idle.pop();
+      const { fn, args, resolve, reject } = this.queue.shift();
+      const id = this.nextId++;
+      this.pending.set(id, { resolve, reject });
+      w.postMessage({ id, fn, args });
+    }
+  }
+
+  // 全 Worker で同じ seed のシャードを回し、`recommend` と同じ形 ({pos, mean, stderr, samples, gap}[]) で返す
+  async recommend(board, card, sims, budgetMs, deck, algo) {
+    const seed = (Math.random() * 2 ** 32) >>> 0;
+    const n = Math.min(this.size, MAX_SHARDS);
+    const parts = await Promise.all(Array.from({ length: n }, (_, k) =>
+      this.call("recommendShard", board, card, sims, seed, k, n, budgetMs, deck, algo)));
+    return mergeRecommend(parts);
+  }
+
+  terminate() { for (const w of this.workers) w.terminate(); }
+}
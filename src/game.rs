@@ -0,0 +1,166 @@
+//! 1 局通しのシミュレーション
+//! ───────────────────────────────────────────────────────────
+//! * ドロー列を先に作り (`draw_sequence`)、方策に 1 枚ずつ置かせる (`play_draws`)
+//! * 同じドロー列を別の方策に渡せば common random numbers で比較できる
+
+use crate::{BOARD_SIZE, GameState, McParams, MoveStat, Policy, ranked, ScoreDist, SimpleRng, Stats, StreamsError, sample_card};
+
+/// 1 手 (引いた札と置いたマス)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move { pub card: u8, pub pos: usize }
+
+/// 1 局の棋譜と結果
+#[derive(Clone, Debug)]
+pub struct GameRecord {
+    pub moves: Vec<Move>,
+    /// 終局盤面
+    pub board: Vec<u8>,
+    pub score: i32,
+}
+
+/// `st` の山札から、空きマスが埋まるか山札が尽きるまでのドロー列を作る
+pub fn draw_sequence(st: &GameState, rng: &mut SimpleRng) -> Vec<u8> {
+    let mut deck = st.deck_count;
+    let mut deck_len = st.deck_len;
+    let n = st.empty_positions().count().min(deck_len as usize);
+    (0..n).map(|_| {
+        let card = sample_card(&deck, deck_len, rng);
+        deck[card as usize] -= 1;
+        deck_len -= 1;
+        card
+    }).collect()
+}
+
+/// 決まったドロー列を `policy` に打たせる。列が空きマスより長ければ余りは無視する。
+/// 山札に無い札が来たらエラー、方策が埋まったマスを選んだら panic
+pub fn play_draws<P: Policy + ?Sized>(st: &GameState, policy: &mut P, draws: &[u8]) -> Result<GameRecord, StreamsError> {
+    let mut st = st.clone();
+    let mut moves = Vec::with_capacity(draws.len());
+    for &card in draws {
+        if st.empty_positions().next().is_none() { break; }
+        if st.deck_count.get(card as usize).is_none_or(|&c| c == 0) {
+            return Err(StreamsError::OverusedCard { card, pos: None });
+        }
+        let pos = policy.choose(&st, card);
+        assert_eq!(st.board().get(pos), Some(&0), "policy chose occupied cell {pos}");
+        st.draw(card);
+        st.place(pos, card);
+        moves.push(Move { card, pos });
+    }
+    Ok(GameRecord { moves, board: st.board().to_vec(), score: st.score() })
+}
+
+/// `st` から終局まで `policy` で打つ。ドローは `rng` から
+pub fn simulate_from<P: Policy + ?Sized>(st: &GameState, policy: &mut P, rng: &mut SimpleRng) -> GameRecord {
+    play_draws(st, policy, &draw_sequence(st, rng)).expect("draws come from the deck")
+}
+
+/// 標準ルールの空盤面から 1 局 (20 手) 打った棋譜
+pub fn simulate_game<P: Policy + ?Sized>(policy: &mut P, rng: &mut SimpleRng) -> GameRecord {
+    let st = GameState::new([0; BOARD_SIZE]).expect("empty board");
+    simulate_from(&st, policy, rng)
+}
+
+/*────────────── 方策の総当たり比較 ─────────────*/
+/// `tournament` の集計
+#[derive(Clone, Debug)]
+pub struct Tournament {
+    pub games: usize,
+    /// 方策ごとの得点 (平均・標準誤差)
+    pub stats: Vec<Stats>,
+    pub dists: Vec<ScoreDist>,
+    /// wins[i][j] = 同じドロー列で i が j を上回った局数 (引き分けは 0.5)
+    pub wins: Vec<Vec<f64>>,
+}
+impl Tournament {
+    /// i の j に対する勝率
+    pub fn win_rate(&self, i: usize, j: usize) -> f64 {
+        if self.games == 0 { return 0.0; }
+        self.wins[i][j] / self.games as f64
+    }
+}
+
+/// `st` から `games` 局、全方策に同じドロー列を配って打たせる (common random numbers)。
+/// ドロー列は `seed` だけで決まるので、方策側の乱数も固定すれば結果は再現する
+pub fn tournament(st: &GameState, policies: &mut [Box<dyn Policy>], games: usize, seed: u64) -> Tournament {
+    let k = policies.len();
+    let mut t = Tournament {
+        games, stats: vec![Stats::default(); k], dists: vec![ScoreDist::default(); k], wins: vec![vec![0.0; k]; k],
+    };
+    let mut rng = SimpleRng::new(seed);
+    let mut scores = vec![0; k];
+    for _ in 0..games {
+        let draws = draw_sequence(st, &mut rng);
+        for (i, pol) in policies.iter_mut().enumerate() {
+            scores[i] = play_draws(st, pol, &draws).expect("draws come from the deck").score;
+            t.stats[i].push(scores[i] as f64);
+            t.dists[i].push(scores[i]);
+        }
+        for i in 0..k {
+            for j in 0..k {
+                if i == j { continue; }
+                t.wins[i][j] += match scores[i].cmp(&scores[j]) {
+                    core::cmp::Ordering::Greater => 1.0,
+                    core::cmp::Ordering::Equal   => 0.5,
+                    core::cmp::Ordering::Less    => 0.0,
+                };
+            }
+        }
+    }
+    t
+}
+
+/*────────────── 多人数 (共通ドロー) ─────────────*/
+// Streams では全員が同じ札を同時に受け取る。各自の山札は常に等しいので、
+// 1 本のドロー列をそれぞれの盤に `play_draws` すれば 1 局になる
+
+/// 各プレイヤー `(盤面, 方策)` に同じドロー列を配って打たせる
+pub fn play_match(players: &mut [(GameState, Box<dyn Policy>)], draws: &[u8]) -> Result<Vec<GameRecord>, StreamsError> {
+    players.iter_mut().map(|(st, pol)| play_draws(st, pol, draws)).collect()
+}
+
+/// 先頭プレイヤーの山札からドロー列を作って 1 局打つ
+pub fn simulate_match(players: &mut [(GameState, Box<dyn Policy>)], rng: &mut SimpleRng) -> Result<Vec<GameRecord>, StreamsError> {
+    let Some((first, _)) = players.first() else { return Ok(Vec::new()) };
+    let draws = draw_sequence(first, rng);
+    play_match(players, &draws)
+}
+
+/// `scores[i]` の勝ち分: 最高点なら 1 / 同点者数、そうでなければ 0
+pub fn win_share(scores: &[i32], i: usize) -> f64 {
+    let top = scores.iter().copied().max().unwrap_or(i32::MIN);
+    if scores[i] < top { return 0.0; }
+    1.0 / scores.iter().filter(|&&s| s == top).count() as f64
+}
+
+/// 相手の盤面と方策を仮定して、`card` を各空きマスに置いたときの勝率 (同点は等分) を返す。
+/// 自分の残りは `p.rollout_policy`、相手は各自の方策で打つ。1 試行ごとに残りのドロー列を
+/// 1 本引き、全候補マスと全相手で共有する。`p.sims` 試行 (期限切れ・中断なら 2 試行以降で打ち切り)
+pub fn win_probabilities(me: &GameState, opponents: &mut [(GameState, Box<dyn Policy>)], card: u8,
+                         p: &McParams, rng: &mut SimpleRng) -> Result<Vec<MoveStat>, StreamsError> {
+    let mut st = me.after_draw(card)?;
+    if let Some(player) = opponents.iter().position(|(o, _)| o.deck() != me.deck()) {
+        return Err(StreamsError::DeckMismatch { player });
+    }
+    let empties: Vec<usize> = st.empty_positions().collect();
+    let mut stats = vec![Stats::default(); empties.len()];
+    let mut scores = vec![0; opponents.len() + 1];
+    let mut rollout_policy = p.rollout_policy;
+    for n in 0..p.sims.max(1) {
+        if n >= 2 && p.stopped() { break; }
+        let rest = draw_sequence(&st, rng);
+        let mut draws = Vec::with_capacity(rest.len() + 1);
+        draws.push(card);
+        draws.extend_from_slice(&rest);
+        for (k, (o, pol)) in opponents.iter_mut().enumerate() {
+            scores[k + 1] = play_draws(o, pol, &draws)?.score;
+        }
+        for (s, &pos) in stats.iter_mut().zip(&empties) {
+            st.place(pos, card);
+            scores[0] = play_draws(&st, &mut rollout_policy, &rest)?.score;
+            st.remove(pos);
+            s.push(win_share(&scores, 0));
+        }
+    }
+    Ok(ranked(&empties.into_iter().zip(stats).collect::<Vec<_>>()))
+}
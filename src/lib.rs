@@ -4,18 +4,34 @@
 //! * HashMap → 配列カウンタ
 //! * clone() 排除・バックトラック式再帰
 //! * SimpleRng (xorshift*) を継続使用
+//! * native では `parallel` feature でルートの分岐と大きな rollout を rayon 並列化 (結果は同じ)
 //!
 //! ビルド例
 //! ```bash
 //! wasm-pack build --release --target web
+//! cargo build --release --features parallel   # native 並列版
 //! ```
 
 #[cfg(target_arch = "wasm32")] use js_sys::Float64Array;
 #[cfg(target_arch = "wasm32")] use wasm_bindgen::prelude::*;
 use core::time::Duration;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+mod game;
+mod matrix;
+mod mcts;
+mod policy;
+mod solver;
+pub use game::{Move, GameRecord, draw_sequence, play_draws, simulate_from, simulate_game, Tournament, tournament,
+               play_match, simulate_match, win_share, win_probabilities};
+pub use matrix::{EvMatrix, ev_matrix};
+pub use mcts::ev_mcts;
+pub use policy::{Policy, RandomPolicy, GreedyPolicy, SearchPolicy, policy_by_name, playout_with};
+pub use solver::{Estimate, Solver, SolverStats};
 
 /*─────────────── 定数 ───────────────*/
-pub const BOARD_SIZE: usize = 20;
+pub const BOARD_SIZE: usize = 20;   // 標準ルールの盤長
+pub const MAX_BOARD:  usize = 32;   // Rules で指定できる盤長の上限
 const MIN_CARD: u8 = 1;
 const MAX_CARD: u8 = 30;
 const JOKER:     u8 = 31;   // 内部表現で 31 を Joker に割当て
@@ -26,6 +42,106 @@ const SCORE_TABLE: [i32; BOARD_SIZE + 1] = [
     0,0,1,3,5,7,9,10,15,20,25,30,20,40,50,60,70,50,100,150,300
 ];
 
+/*─────────────── ルール ───────────────*/
+/// run として続くための隣接条件
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RunOrder {
+    /// prev <= v (標準)
+    #[default]
+    NonDecreasing,
+    /// prev < v
+    Increasing,
+}
+
+/// 盤長・山札構成・得点表・run の単調性。標準ルールは `Rules::default()`
+#[derive(Clone, Copy, Debug)]
+pub struct Rules {
+    /// 盤のマス数 (<= MAX_BOARD)
+    pub board_len: usize,
+    /// 札ごとの枚数 (index 1‒30 = 数札, 31 = Joker, 0 は未使用)
+    pub deck: [u8; 32],
+    /// index == run 長, value == 得点
+    pub score_table: [i32; MAX_BOARD + 1],
+    pub run_order: RunOrder,
+}
+impl Rules {
+    pub fn standard() -> Self {
+        let mut deck = [0u8; 32];
+        for n in MIN_CARD..=MAX_CARD {
+            deck[n as usize] = if DUPLICATE_RANGE.contains(&n) { DUPLICATE_COUNT } else { 1 };
+        }
+        deck[JOKER as usize] = 1;
+        let mut score_table = [0i32; MAX_BOARD + 1];
+        score_table[..=BOARD_SIZE].copy_from_slice(&SCORE_TABLE);
+        Self { board_len: BOARD_SIZE, deck, score_table, run_order: RunOrder::NonDecreasing }
+    }
+
+    #[inline] fn continues(&self, prev: i32, v: i32) -> bool {
+        match self.run_order {
+            RunOrder::NonDecreasing => v >= prev,
+            RunOrder::Increasing    => v >  prev,
+        }
+    }
+
+    /// Score a board without allocations。
+    /// Joker は任意の値を取るワイルドカードで、得点が最大になる値として数える
+    #[inline] pub fn score_board(&self, board: &[u8]) -> i32 {
+        self.score_from(board, 0, None, 0, 0)
+    }
+
+    /// `board[i..]` を走査。Joker に当たったら候補値ごとに分岐して最大を取る
+    fn score_from(&self, board: &[u8], i: usize, mut last: Option<i32>, mut len: usize, mut acc: i32) -> i32 {
+        for (k, &cell) in board.iter().enumerate().skip(i) {
+            let v = match cell {
+                0 => {
+                    if len > 0 { acc += self.score_table[len]; }
+                    len = 0; last = None;
+                    continue;
+                },
+                JOKER => {
+                    // 同じ区間で次に現れる数札
+                    let next = board[k + 1..].iter().take_while(|&&c| c != 0)
+                        .find(|&&c| c != JOKER).map(|&c| c as i32);
+                    let mut best = i32::MIN;
+                    for v in joker_candidates(last, next) {
+                        let (l, a) = self.extend(last, len, acc, v);
+                        best = best.max(self.score_from(board, k + 1, Some(v), l, a));
+                    }
+                    return best;
+                },
+                n => n as i32,
+            };
+            (len, acc) = self.extend(last, len, acc, v);
+            last = Some(v);
+        }
+        if len > 0 { acc += self.score_table[len]; }
+        acc
+    }
+
+    /// 値 `v` のマスを 1 つ進めたときの (run 長, 確定済み得点)
+    #[inline] fn extend(&self, last: Option<i32>, len: usize, acc: i32, v: i32) -> (usize, i32) {
+        match last {
+            Some(prev) if self.continues(prev, v) => (len + 1, acc),
+            _ => (1, if len > 0 { acc + self.score_table[len] } else { acc }),
+        }
+    }
+}
+
+/// Joker の値の代表。比較結果 (直前と続くか・次の数札と続くか) の全組合せを
+/// 実現できる値だけを試せば十分: 端値と、直前値・次の数札の ±1
+fn joker_candidates(last: Option<i32>, next: Option<i32>) -> impl Iterator<Item = i32> {
+    let mut vals = [MIN_CARD as i32, MAX_CARD as i32, 0, 0, 0, 0, 0, 0];
+    let mut n = 2;
+    for x in [last, next].into_iter().flatten() {
+        for v in [x - 1, x, x + 1] {
+            let v = v.clamp(MIN_CARD as i32, MAX_CARD as i32);
+            if !vals[..n].contains(&v) { vals[n] = v; n += 1; }
+        }
+    }
+    vals.into_iter().take(n)
+}
+impl Default for Rules { fn default() -> Self { Self::standard() } }
+
 /*─────────────── PRNG ───────────────*/
 #[derive(Clone, Copy)]
 pub struct SimpleRng(u64);
@@ -36,6 +152,13 @@ impl SimpleRng {
         x.wrapping_mul(0x2545F4914F6CDD1D)
     }
     #[inline] pub fn gen_range(&mut self, upper: u8) -> u8 { (self.next_u64() as u8) % upper }
+    /// `stream` 番目の独立な系列 (splitmix64 で混ぜる)。自分の状態は進めない
+    pub fn fork(&self, stream: u64) -> Self {
+        let mut z = self.0 ^ stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self::new(z ^ (z >> 31))
+    }
 }
 impl Default for SimpleRng {
     fn default() -> Self {
@@ -52,33 +175,149 @@ impl Default for SimpleRng {
     }
 }
 
+/*─────────────── 並列化 ───────────────*/
+// ルートの分岐は常に「親の乱数から 1 語引いた種 → fork(i)」で系列を分け、添字順に合算する。
+// `parallel` feature の有無で変わるのは実行の仕方だけで、同じ種なら結果は同じ
+
+/// `f(0), …, f(n-1)` を添字順に返す (`parallel` なら rayon で並列)
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+fn par_map<T: Send>(n: usize, f: impl Fn(usize) -> T + Sync + Send) -> Vec<T> {
+    use rayon::prelude::*;
+    (0..n).into_par_iter().map(f).collect()
+}
+#[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+fn par_map<T>(n: usize, f: impl Fn(usize) -> T) -> Vec<T> {
+    (0..n).map(f).collect()
+}
+
+/*─────────────── 時計 ───────────────*/
+/// 壁時計 (ms)。Wasm では Date.now() を使う
+#[inline] fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")] { js_sys::Date::now() }
+    #[cfg(not(target_arch = "wasm32"))] {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs_f64() * 1e3
+    }
+}
+
+/// 探索の打ち切り時刻
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline(f64);
+impl Deadline {
+    pub fn after(budget: Duration) -> Self { Self(now_ms() + budget.as_secs_f64() * 1e3) }
+    #[inline] pub fn expired(&self) -> bool { now_ms() >= self.0 }
+    /// 残り時間を `parts` 等分した最初の区切り
+    pub fn split(&self, parts: usize) -> Self {
+        let now = now_ms();
+        Self(now + (self.0 - now).max(0.0) / parts.max(1) as f64)
+    }
+}
+
+/*─────────────── エラー ───────────────*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamsError {
+    /// 盤面の長さがルールと一致しない
+    BadLength { expected: usize, found: usize },
+    /// 解釈できない文字 (`pos` は文字位置)
+    BadChar { pos: usize, ch: char },
+    /// 山札の枚数を超えて使われた札 (`pos` は超過したマス、盤外なら None)
+    OverusedCard { card: u8, pos: Option<usize> },
+    /// 置ける空きマスが無い
+    FullBoard,
+    /// 共通ドローの対局で `player` の山札が他と食い違う
+    DeckMismatch { player: usize },
+    /// 盤外か、既に札のあるマス
+    BadPosition { pos: usize },
+    /// ルールの山札が不正: index 0 に札がある (`slot0` 枚) か、合計 `total` が 255 枚を超える
+    BadDeck { total: usize, slot0: u8 },
+}
+impl core::fmt::Display for StreamsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StreamsError::BadLength { expected, found } =>
+                write!(f, "board must be {expected} cells, got {found}"),
+            StreamsError::BadChar { pos, ch } => write!(f, "bad char {ch:?} at {pos}"),
+            StreamsError::OverusedCard { card, pos: Some(pos) } =>
+                write!(f, "card {card} at {pos} exceeds its count in the deck"),
+            StreamsError::OverusedCard { card, pos: None } =>
+                write!(f, "card {card} is used more times than the deck holds"),
+            StreamsError::FullBoard => write!(f, "board has no empty cell"),
+            StreamsError::DeckMismatch { player } => write!(f, "player {player} does not share the deck"),
+            StreamsError::BadPosition { pos } => write!(f, "cell {pos} is not an empty cell"),
+            StreamsError::BadDeck { total, slot0 } =>
+                write!(f, "rules deck must hold at most 255 cards with none at index 0 (got {total}, {slot0} at index 0)"),
+        }
+    }
+}
+impl std::error::Error for StreamsError {}
+
 /*─────────────── 基本型 ───────────────*/
 #[derive(Clone, Debug)]
 pub struct GameState {
-    board: [u8; BOARD_SIZE],   // 0 = 空, 1‒30 = 数札, 31 = Joker
+    board: [u8; MAX_BOARD],    // 0 = 空, 1‒30 = 数札, 31 = Joker (board_len 以降は常に 0)
     deck_count: [u8; 32],      // 残っている札の枚数
-    deck_len:  u8,             // 未ドロー枚数 (<= 63)
+    deck_len:  u8,             // 未ドロー枚数
+    rules: Rules,
+    best_split: [i32; MAX_BOARD + 1], // 長さ n の区間を連に分けたときの最大得点 (upper_bound 用)
 }
 
 impl GameState {
-    pub fn new(board: [u8; BOARD_SIZE]) -> Self {
-        // 初期山札枚数を設定
-        let mut deck_count = [0u8; 32];
-        for n in MIN_CARD..=MAX_CARD {
-            deck_count[n as usize] = if DUPLICATE_RANGE.contains(&n) { DUPLICATE_COUNT } else { 1 };
+    /// 標準ルールの盤面
+    pub fn new(board: [u8; BOARD_SIZE]) -> Result<Self, StreamsError> {
+        Self::with_rules(Rules::default(), &board)
+    }
+
+    /// 任意ルールの盤面。山札は「ルールの全札 − 盤上の札」
+    pub fn with_rules(rules: Rules, board: &[u8]) -> Result<Self, StreamsError> {
+        if rules.board_len > MAX_BOARD {
+            return Err(StreamsError::BadLength { expected: MAX_BOARD, found: rules.board_len });
         }
-        deck_count[JOKER as usize] = 1;
+        if board.len() != rules.board_len {
+            return Err(StreamsError::BadLength { expected: rules.board_len, found: board.len() });
+        }
+        // deck_len は u8、index 0 は「空」なので引けない
+        let total = rules.deck.iter().map(|&x| x as usize).sum::<usize>();
+        if total > u8::MAX as usize || rules.deck[0] != 0 {
+            return Err(StreamsError::BadDeck { total, slot0: rules.deck[0] });
+        }
+        let mut arr = [0u8; MAX_BOARD];
+        arr[..board.len()].copy_from_slice(board);
+        // 初期山札枚数を設定
+        let mut deck_count = rules.deck;
         // 盤面に置かれているカードを山札から減算
-        for &c in &board {
+        for (pos, &c) in board.iter().enumerate() {
             if c != 0 {
-                let ix = c as usize;
-                deck_count[ix] -= 1;
+                let overused = StreamsError::OverusedCard { card: c, pos: Some(pos) };
+                let cnt = deck_count.get_mut(c as usize).ok_or(overused.clone())?;
+                *cnt = cnt.checked_sub(1).ok_or(overused)?;
             }
         }
-        let deck_len = deck_count.iter().map(|&x| x as u16).sum::<u16>() as u8;
-        Self { board, deck_count, deck_len }
+        let deck_len = deck_count.iter().sum::<u8>();
+        let mut best_split = [0i32; MAX_BOARD + 1];
+        for n in 1..=rules.board_len {
+            // 最後のマスは「空のまま (0 点)」か「長さ k の連の末尾」
+            best_split[n] = (1..=n).map(|k| best_split[n - k] + rules.score_table[k]).fold(best_split[n - 1], i32::max);
+        }
+        Ok(Self { board: arr, deck_count, deck_len, rules, best_split })
+    }
+
+    /// 残り山札を明示した盤面。公開済みで未配置の札や欠品を表現できる。
+    /// 各札について「盤上 + 山札」がルールの枚数を超えてはならない
+    pub fn with_deck(rules: Rules, board: &[u8], deck: [u8; 32]) -> Result<Self, StreamsError> {
+        let st = Self::with_rules(rules, board)?;
+        if let Some(card) = (0..32).find(|&c| deck[c] > st.deck_count[c]) {
+            return Err(StreamsError::OverusedCard { card: card as u8, pos: None });
+        }
+        let deck_len = deck.iter().map(|&x| x as u16).sum::<u16>() as u8;
+        Ok(Self { deck_count: deck, deck_len, ..st })
     }
 
+    #[inline] pub fn board(&self) -> &[u8] { &self.board[..self.rules.board_len] }
+    #[inline] pub fn rules(&self) -> &Rules { &self.rules }
+    /// 札ごとの残り枚数 (index 31 = Joker)
+    #[inline] pub fn deck(&self) -> &[u8; 32] { &self.deck_count }
+    #[inline] pub fn deck_len(&self) -> usize { self.deck_len as usize }
+
     #[inline] fn place(&mut self, pos: usize, card: u8) {
         debug_assert_eq!(self.board[pos], 0);
         self.board[pos] = card;
@@ -86,154 +325,1162 @@ impl GameState {
     #[inline] fn remove(&mut self, pos: usize) {
         self.board[pos] = 0;
     }
+    /// 山札から `card` を 1 枚抜く (残っていなければ false)
+    #[inline] fn draw(&mut self, card: u8) -> bool {
+        let Some(c) = self.deck_count.get_mut(card as usize).filter(|c| **c > 0) else { return false };
+        *c -= 1;
+        self.deck_len -= 1;
+        true
+    }
+    #[inline] fn undraw(&mut self, card: u8) {
+        self.deck_count[card as usize] += 1;
+        self.deck_len += 1;
+    }
+    /// `card` を引いた直後 (まだ置いていない) の局面
+    fn after_draw(&self, card: u8) -> Result<Self, StreamsError> {
+        if self.empty_positions().next().is_none() { return Err(StreamsError::FullBoard); }
+        let mut st = self.clone();
+        if !st.draw(card) { return Err(StreamsError::OverusedCard { card, pos: None }); }
+        Ok(st)
+    }
 
     /// Score current board without allocations
-    pub fn score(&self) -> i32 {
-        let mut runs = [0u8; BOARD_SIZE];
-        let mut runs_len = 0usize;
-        let mut len: u8 = 0;
-        let mut last_val: Option<i32> = None;
-        for &cell in &self.board {
-            let val = match cell {
-                0       => None,
-                JOKER   => last_val,
-                n       => Some(n as i32),
-            };
-            match (val, last_val) {
-                (Some(v), Some(prev)) if v >= prev => { len += 1; },
-                (Some(_), Some(_))                 => { runs[runs_len] = len; runs_len += 1; len = 1; },
-                (Some(_), None)                    => { len = 1; },
-                (None, _)                          => { if len > 0 { runs[runs_len] = len; runs_len += 1; } len = 0; },
-            }
-            last_val = val;
+    #[inline] pub fn score(&self) -> i32 { self.rules.score_board(self.board()) }
+
+    /// 空きマスをどう埋めても超えない得点 (許容的な上界)。隣り合う数札が連を成さない所では
+    /// 必ず連が切れるのでそこで区間に分け、空きの無い区間は実際の得点、空きのある区間は
+    /// 「その長さを連に分けたときの最大得点」で見積もる
+    pub fn upper_bound(&self) -> i32 {
+        let b = self.board();
+        let is_num = |c: u8| c != 0 && c != JOKER;
+        let mut total = 0;
+        let mut start = 0;
+        for i in 1..=b.len() {
+            let cut = i == b.len() || (is_num(b[i - 1]) && is_num(b[i]) && !self.rules.continues(b[i - 1] as i32, b[i] as i32));
+            if !cut { continue; }
+            let seg = &b[start..i];
+            total += if seg.contains(&0) { self.best_split[seg.len()] } else { self.rules.score_board(seg) };
+            start = i;
+        }
+        total
+    }
+
+    /// 置換表用の正準キー (盤面 + 残り山札の FNV-1a)。ルールは含まない
+    pub fn key(&self) -> u64 {
+        let mut h = 0xCBF2_9CE4_8422_2325u64;
+        for &b in self.board().iter().chain(self.deck_count.iter()) {
+            h = (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3);
         }
-        if len > 0 { runs[runs_len] = len; runs_len += 1; }
-        let mut sum = 0i32;
-        for i in 0..runs_len { sum += SCORE_TABLE[runs[i] as usize]; }
-        sum
+        h.max(1) // 0 は空スロット用に予約
     }
 
     /// 盤上の空きマスの iterator
     #[inline] fn empty_positions<'a>(&'a self) -> impl Iterator<Item=usize> + 'a {
-        self.board.iter().enumerate().filter_map(|(i, &c)| (c==0).then(|| i))
+        self.board().iter().enumerate().filter(|&(_, &c)| c == 0).map(|(i, _)| i)
+    }
+}
+
+/*────────────── 目的関数 ─────────────*/
+/// 探索が最大化する量。いずれも最終得点の効用の期待値として扱う
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Objective {
+    /// 得点の期待値
+    #[default]
+    Mean,
+    /// P(score >= target)
+    AtLeast(i32),
+    /// 下側 `alpha` の CVaR。Rockafellar–Uryasev の u(x) = η − (η − x)⁺ / α を最大化し、
+    /// 値は CVaR の下界になる。η には `Objective::cvar` で VaR の推定値を入れる
+    Cvar { alpha: f64, eta: f64 },
+    /// 任意の効用関数
+    Utility(fn(i32) -> f64),
+}
+impl Objective {
+    #[inline] pub fn utility(&self, score: i32) -> f64 {
+        match *self {
+            Objective::Mean              => score as f64,
+            Objective::AtLeast(t)        => if score >= t { 1.0 } else { 0.0 },
+            Objective::Cvar { alpha, eta } => eta - (eta - score as f64).max(0.0) / alpha,
+            Objective::Utility(f)        => f(score),
+        }
+    }
+    /// 最終得点が `ub` 以下のときの効用の上界。効用が得点に単調でなければ None
+    #[inline] pub fn bound(&self, ub: i32) -> Option<f64> {
+        match self {
+            Objective::Utility(_) => None,
+            _ => Some(self.utility(ub)),
+        }
+    }
+    /// 現盤面からの rollout 分布の `alpha` 分位点を η とした CVaR 目的
+    pub fn cvar(alpha: f64, st: &GameState, p: &McParams, rng: &mut SimpleRng) -> Self {
+        let alpha = alpha.clamp(1e-6, 1.0);
+        let eta = score_distribution(st, p, rng).quantile(alpha) as f64;
+        Objective::Cvar { alpha, eta }
     }
 }
 
 /*────────────── Monte-Carlo Hybrid ─────────────*/
+/// rollout 中の配置方策
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RolloutPolicy {
+    /// 引いた順に左から埋める (= 置き場所を選ばない)
+    #[default]
+    LeftToRight,
+    /// 両隣の値の間で比例配分した位置に置く (`proportional_slot`)
+    Proportional,
+}
+
+/// `evaluate` が使う探索法
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    /// 深さ rollout_limit まで全展開 + rollout
+    #[default]
+    Hybrid,
+    /// UCT (ドロー = chance node, 配置 = decision node)
+    Mcts,
+}
+
 #[derive(Clone, Copy)]
-pub struct McParams { pub sims: usize, pub rollout_limit: usize }
-impl Default for McParams { fn default() -> Self { Self { sims: 5, rollout_limit: 1 } } }
+pub struct McParams<'a> {
+    pub sims: usize,
+    pub rollout_limit: usize,
+    /// None なら時間無制限。期限切れ後の ev_before_draw の戻り値は不正確
+    pub deadline: Option<Deadline>,
+    /// 中断フラグ。立つと期限切れと同じく各探索が打ち切られ、rollout も途中で止まる
+    pub cancel: Option<&'a AtomicBool>,
+    pub objective: Objective,
+    pub algorithm: Algorithm,
+    /// Mcts の反復回数 (期限があれば先に来た方で止まる)
+    pub iterations: usize,
+    /// rollout で札をどこに置くか (探索の rollout は高速化のためこの 2 種だけ。任意の `Policy` は `playout_with` で)
+    pub rollout_policy: RolloutPolicy,
+    /// 空きマスがこれ未満なら `evaluate` は厳密解に切り替える (0 で無効)
+    pub exact_threshold: usize,
+    /// 非可逆 (lossy) な近似。ドローを盤面に対する札の同値類 (`card_classes`) ごとに 1 回だけ展開する。
+    /// 空きが 1 マスの局面以外では値がずれる (手元の測定で数 %)。既定は false
+    pub lossy_abstraction: bool,
+    /// 配置の分岐で上界 (`GameState::upper_bound`) による枝刈りをする
+    pub pruning: bool,
+}
+impl Default for McParams<'_> {
+    fn default() -> Self {
+        Self {
+            sims: 5, rollout_limit: 1, deadline: None, cancel: None, objective: Objective::Mean,
+            algorithm: Algorithm::Hybrid, iterations: 20_000, rollout_policy: RolloutPolicy::LeftToRight,
+            exact_threshold: 5, lossy_abstraction: false, pruning: true,
+        }
+    }
+}
+impl McParams<'_> {
+    /// 中断が要求されたか
+    #[inline] pub fn cancelled(&self) -> bool { self.cancel.is_some_and(|c| c.load(Ordering::Relaxed)) }
+    /// 中断されたか期限切れ
+    #[inline] pub(crate) fn stopped(&self) -> bool { self.cancelled() || self.deadline.is_some_and(|d| d.expired()) }
+}
+
+/// 途中経過: `total` 段階中 `done` 段階目まで終わった時点の推定値
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Progress { pub done: usize, pub total: usize, pub estimate: f64 }
+
+/// 評価値を出した探索法
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolveMode { Exact, Hybrid, Mcts }
+
+/// 配置の分岐で展開した子と、上界で切った子の数
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneStats { pub expanded: u64, pub pruned: u64 }
+impl PruneStats {
+    fn merge(&mut self, o: &PruneStats) { self.expanded += o.expanded; self.pruned += o.pruned; }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Evaluation { pub value: f64, pub mode: SolveMode, pub prune: PruneStats }
+
+/// 終盤の自動厳密解で使う置換表の大きさ
+pub const ENDGAME_TT_BYTES: usize = 8 << 20;
+
+/// ドロー前の局面を評価する (`evaluate_with_mode` の値だけ)
+pub fn evaluate(st: &mut GameState, p: &McParams, rng: &mut SimpleRng) -> f64 {
+    evaluate_with_mode(st, p, rng).value
+}
+
+/// 空きマスが `p.exact_threshold` 未満なら厳密解、そうでなければ `p.algorithm` で評価し、
+/// どちらを使ったかも返す。厳密解が期限切れ・中断で終わらなければ `p.algorithm` に切り替える
+pub fn evaluate_with_mode(st: &mut GameState, p: &McParams, rng: &mut SimpleRng) -> Evaluation {
+    evaluate_with_progress(st, p, rng, &mut |_| {})
+}
+
+/// `evaluate_with_mode` に途中経過の通知を付けたもの。Hybrid は反復 (深さ) ごと、
+/// Mcts は 1024 反復ごと、厳密解は最後に 1 回 `on_progress` を呼ぶ。
+/// 呼び出し側は `p.cancel` のフラグを立てて打ち切れる (それまでの推定値が返る)
+pub fn evaluate_with_progress(st: &mut GameState, p: &McParams, rng: &mut SimpleRng,
+                              on_progress: &mut dyn FnMut(&Progress)) -> Evaluation {
+    evaluate_in(st, p, rng, &mut TransTable::new(ENDGAME_TT_BYTES), on_progress)
+}
+
+/// `evaluate_with_progress` の本体。厳密解の置換表は呼び出し側が持ち、複数の局面で使い回す
+/// (確保は最初の store までされない)
+fn evaluate_in(st: &mut GameState, p: &McParams, rng: &mut SimpleRng, tt: &mut TransTable,
+               on_progress: &mut dyn FnMut(&Progress)) -> Evaluation {
+    if let Some(value) = endgame_exact(st, p, tt) {
+        on_progress(&Progress { done: 1, total: 1, estimate: value });
+        return Evaluation { value, mode: SolveMode::Exact, prune: tt.prune };
+    }
+    let mut prune = PruneStats::default();
+    match p.algorithm {
+        Algorithm::Hybrid => Evaluation { value: anytime(st, p, rng, &mut prune, on_progress), mode: SolveMode::Hybrid, prune },
+        Algorithm::Mcts   => Evaluation { value: mcts::search(st, p, rng, on_progress), mode: SolveMode::Mcts, prune },
+    }
+}
+
+/// `card` を引いて各空きマスに置いた局面を `evaluate` した値 (盤の長さ、埋まったマスは 0)。
+/// `card` は山札から抜いてから評価する。期限は未評価のマスで等分し、1 マスごとに通知する
+pub fn evaluate_after_card(st: &GameState, card: u8, p: &McParams, rng: &mut SimpleRng,
+                           on_progress: &mut dyn FnMut(&Progress)) -> Result<Vec<f64>, StreamsError> {
+    let mut st = st.after_draw(card)?;
+    let mut q = *p;
+    let mut tt = TransTable::new(ENDGAME_TT_BYTES);
+    let mut vals = vec![0.0f64; st.board().len()];
+    let empties: Vec<usize> = st.empty_positions().collect();
+    for (i, &pos) in empties.iter().enumerate() {
+        if p.cancelled() { break; }
+        // 残り時間を未評価のマスで等分
+        q.deadline = p.deadline.map(|d| d.split(empties.len() - i));
+        st.place(pos, card);
+        vals[pos] = evaluate_in(&mut st, &q, rng, &mut tt, &mut |_| {}).value;
+        st.remove(pos);
+        on_progress(&Progress { done: i + 1, total: empties.len(), estimate: vals[pos] });
+    }
+    Ok(vals)
+}
 
 pub fn ev_before_draw(st: &mut GameState, p: &McParams, rng: &mut SimpleRng, level: usize) -> f64 {
+    before_draw(st, p, rng, level, &mut PruneStats::default())
+}
+
+fn before_draw(st: &mut GameState, p: &McParams, rng: &mut SimpleRng, level: usize, prune: &mut PruneStats) -> f64 {
     if st.deck_len == 0 || st.empty_positions().next().is_none() {
-        return st.score() as f64;
+        return p.objective.utility(st.score());
     }
     if level >= p.rollout_limit {
         return rollout(st, p, rng);
     }
+    if p.stopped() {
+        return p.objective.utility(st.score()); // 打ち切り: 呼び出し側で破棄される
+    }
     let mut ev = 0.0f64;
     let deck_len_f = st.deck_len as f64;
-    for card in 1u8..=JOKER {
-        let cnt = st.deck_count[card as usize];
-        if cnt == 0 { continue; }
+    let (draws, n) = if p.lossy_abstraction { card_classes(st) } else { card_counts(st) };
+    if level == 0 {
+        // ルート: 札ごとに独立な乱数系列で (並列に) 展開
+        let base = SimpleRng::new(rng.next_u64());
+        let root = &*st;
+        let children = par_map(n, |i| {
+            let (card, _) = draws[i];
+            let mut st = root.clone();
+            let mut prune = PruneStats::default();
+            st.draw(card);
+            (after_draw(&mut st, card, p, &mut base.fork(i as u64), level, &mut prune), prune)
+        });
+        for (&(_, cnt), (child_ev, pr)) in draws[..n].iter().zip(&children) {
+            ev += (cnt as f64 / deck_len_f) * child_ev;
+            prune.merge(pr);
+        }
+        return ev;
+    }
+    for &(card, cnt) in &draws[..n] {
         // ドローしたと仮定して山札を更新
-        st.deck_count[card as usize] -= 1;
-        st.deck_len -= 1;
-        let child_ev = ev_after_draw(st, card, p, rng, level);
+        st.draw(card);
+        let child_ev = after_draw(st, card, p, rng, level, prune);
         // 期待値へ加算
         ev += (cnt as f64 / deck_len_f) * child_ev;
         // 巻き戻し
-        st.deck_count[card as usize] += 1;
-        st.deck_len += 1;
+        st.undraw(card);
     }
     ev
 }
 
-fn ev_after_draw(st: &mut GameState, card: u8, p: &McParams, rng: &mut SimpleRng, level: usize) -> f64 {
+/// 残っている札と枚数 (札ごと)
+fn card_counts(st: &GameState) -> ([(u8, u8); 32], usize) {
+    let mut out = [(0u8, 0u8); 32];
+    let mut n = 0;
+    for card in 1u8..=JOKER {
+        let cnt = st.deck_count[card as usize];
+        if cnt > 0 { out[n] = (card, cnt); n += 1; }
+    }
+    (out, n)
+}
+
+/// 盤面に対する札の同値類: (代表札, 類の合計枚数)。盤上の数札の値を区切りとして、
+/// 同じ値か同じ開区間に入る札をまとめる (盤に Joker が j 枚あれば区切りの ±j と両端の j 個も区切る)。
+/// 空きが 1 マスなら同じ類の札は必ず同じ得点になるので厳密 (`ev_exact` はここでだけ使う)。
+/// それより前は非可逆: 値の違う札は後の札との並びで区別されるのに、代表札 1 枚で
+/// 類全体を引いたことにするので残りの山札も偏る。`McParams::lossy_abstraction` でだけ使う
+fn card_classes(st: &GameState) -> ([(u8, u8); 32], usize) {
+    let board = st.board();
+    let jokers = board.iter().filter(|&&c| c == JOKER).count() as i32;
+    let mut cut = [false; 32];
+    for &c in board.iter().filter(|&&c| c != 0 && c != JOKER) {
+        for v in c as i32 - jokers..=c as i32 + jokers { cut[v.clamp(0, 31) as usize] = true; }
+    }
+    for k in 0..jokers {
+        cut[(MIN_CARD as i32 + k).min(31) as usize] = true;
+        cut[(MAX_CARD as i32 - k).max(0) as usize] = true;
+    }
+    let mut out = [(0u8, 0u8); 32];
+    let mut n = 0;
+    let mut v = MIN_CARD;
+    while v <= MAX_CARD {
+        // [v, w) が 1 つの類
+        let w = if cut[v as usize] { v + 1 } else { (v + 1..=MAX_CARD).find(|&u| cut[u as usize]).unwrap_or(MAX_CARD + 1) };
+        let total: u8 = (v..w).map(|c| st.deck_count[c as usize]).sum();
+        if total > 0 {
+            // 代表は残っている札のうち区間の中央に最も近いもの
+            let mid = (v + w - 1) as f64 / 2.0;
+            let rep = (v..w).filter(|&c| st.deck_count[c as usize] > 0)
+                .min_by(|&a, &b| (a as f64 - mid).abs().total_cmp(&(b as f64 - mid).abs())).unwrap_or(v);
+            out[n] = (rep, total);
+            n += 1;
+        }
+        v = w;
+    }
+    if st.deck_count[JOKER as usize] > 0 { out[n] = (JOKER, st.deck_count[JOKER as usize]); n += 1; }
+    (out, n)
+}
+
+/// 期限まで「展開を 1 段深く」「rollout 倍増」を交互に繰り返し、
+/// 最後に完走した反復の推定値を返す (期限なしなら ev_before_draw と同じ)
+pub fn ev_anytime(st: &mut GameState, p: &McParams, rng: &mut SimpleRng) -> f64 {
+    anytime(st, p, rng, &mut PruneStats::default(), &mut |_| {})
+}
+
+/// 完走した反復ごとに `Progress { done: 反復数, total: 最大反復数 }` を通知する
+/// (空きマス数 e まで 1 段ずつ深くし、その間に倍増を e − 1 回挟むので最大 2e − 1 回)
+fn anytime(st: &mut GameState, p: &McParams, rng: &mut SimpleRng, prune: &mut PruneStats,
+           on_progress: &mut dyn FnMut(&Progress)) -> f64 {
+    let Some(deadline) = p.deadline else {
+        let v = before_draw(st, p, rng, 0, prune);
+        on_progress(&Progress { done: 1, total: 1, estimate: v });
+        return v;
+    };
+    // 深さ 0 (rollout のみ) は期限に関係なく完走させる (中断は効く)
+    let mut q = McParams { rollout_limit: 0, deadline: None, ..*p };
+    let mut best = before_draw(st, &q, rng, 0, prune);
+    q.deadline = Some(deadline);
+    let empties = st.empty_positions().count();
+    let total = (2 * empties).saturating_sub(1);
+    let mut done = 0;
+    on_progress(&Progress { done, total, estimate: best });
+    let mut deepen = true;
+    while q.rollout_limit < empties && !q.stopped() {
+        if deepen { q.rollout_limit += 1 } else { q.sims *= 2 }
+        deepen = !deepen;
+        let v = before_draw(st, &q, rng, 0, prune);
+        if q.stopped() { break; }
+        best = v;
+        done += 1;
+        on_progress(&Progress { done, total, estimate: best });
+    }
+    best
+}
+
+fn after_draw(st: &mut GameState, card: u8, p: &McParams, rng: &mut SimpleRng, level: usize, prune: &mut PruneStats) -> f64 {
     let mut best = f64::NEG_INFINITY;
-    // 空きマスに置いて最大値を取る
-    let empties: Vec<usize> = st.empty_positions().collect();
-    for pos in empties {
+    // 空きマスに置いて効用の期待値が最大のものを取る。上界の高い順に見て、
+    // 上界が暫定最善以下になったら残りは全部切る
+    let (moves, n) = ordered_moves(st, card, &p.objective, p.pruning);
+    for (k, &(ub, pos)) in moves[..n].iter().enumerate() {
+        if ub <= best { prune.pruned += (n - k) as u64; break; }
+        prune.expanded += 1;
         st.place(pos, card);
-        best = best.max(ev_before_draw(st, p, rng, level + 1));
+        best = best.max(before_draw(st, p, rng, level + 1, prune));
         st.remove(pos);
     }
     best
 }
 
+/// 空きマスと「そこに置いた後の効用の上界」を上界の降順で (枝刈りしないなら上界は ∞ で盤の順)
+fn ordered_moves(st: &mut GameState, card: u8, obj: &Objective, pruning: bool) -> ([(f64, usize); MAX_BOARD], usize) {
+    let mut moves = [(f64::INFINITY, 0usize); MAX_BOARD];
+    let mut n = 0;
+    for pos in 0..st.rules.board_len {
+        if st.board[pos] != 0 { continue; }
+        moves[n].1 = pos;
+        n += 1;
+    }
+    // 1 マスしかなければ選択肢は無い
+    if !pruning || n < 2 { return (moves, n); }
+    for m in &mut moves[..n] {
+        st.place(m.1, card);
+        m.0 = obj.bound(st.upper_bound()).unwrap_or(f64::INFINITY);
+        st.remove(m.1);
+    }
+    moves[..n].sort_by(|a, b| b.0.total_cmp(&a.0));
+    (moves, n)
+}
+
+/// これ以上の試行は ROLLOUT_CHUNK 本ずつ独立系列に分ける (並列化の単位)
+const ROLLOUT_CHUNK: usize = 1024;
+
+/// 中断フラグは 256 試行ごと (大きな rollout ではチャンクごと) に見て、それまでの平均を返す
 fn rollout(st: &GameState, p: &McParams, rng: &mut SimpleRng) -> f64 {
-    let mut sum = 0.0f64;
-    for _ in 0..p.sims {
-        // ローカルコピー (64byte 未満なのでコピーの方が速い)
-        let mut board = st.board;
-        let mut deck = st.deck_count;
-        let mut deck_len = st.deck_len;
-        // 盤を埋め尽くす
-        for pos in 0..BOARD_SIZE {
-            if board[pos] != 0 { continue; }
-            // n 番目のカードを引く
-            let idx = rng.gen_range(deck_len);
-            let mut acc = 0u8;
-            let mut drawn = 0u8;
-            for card in 1u8..=JOKER {
-                let c = deck[card as usize];
-                if acc + c > idx { drawn = card; break; }
-                acc += c;
+    if p.sims < 2 * ROLLOUT_CHUNK {
+        let mut sum = 0.0f64;
+        let mut n = 0;
+        for i in 0..p.sims {
+            if i > 0 && i % 256 == 0 && p.cancelled() { break; }
+            sum += p.objective.utility(playout(st, p.rollout_policy, rng));
+            n += 1;
+        }
+        return sum / n.max(1) as f64;
+    }
+    let base = SimpleRng::new(rng.next_u64());
+    let sums = par_map(p.sims.div_ceil(ROLLOUT_CHUNK), |i| {
+        if i > 0 && p.cancelled() { return (0.0, 0); }
+        let mut rng = base.fork(i as u64);
+        let k = ROLLOUT_CHUNK.min(p.sims - i * ROLLOUT_CHUNK);
+        ((0..k).map(|_| p.objective.utility(playout(st, p.rollout_policy, &mut rng))).sum::<f64>(), k)
+    });
+    let (sum, n) = sums.iter().fold((0.0, 0), |(s, n), &(a, k)| (s + a, n + k));
+    sum / n.max(1) as f64
+}
+
+/// 山札が尽きるか盤が埋まるまでランダムにドローし、`policy` で置いた最終得点 (1 試行)
+fn playout(st: &GameState, policy: RolloutPolicy, rng: &mut SimpleRng) -> i32 {
+    // ローカルコピー (64byte 未満なのでコピーの方が速い)
+    let len = st.rules.board_len;
+    let mut board = st.board;
+    let mut deck = st.deck_count;
+    let mut deck_len = st.deck_len;
+    let mut empties = board[..len].iter().filter(|&&c| c == 0).count();
+    // 盤を埋め尽くす
+    while empties > 0 && deck_len > 0 {
+        let drawn = sample_card(&deck, deck_len, rng);
+        // デッキ更新
+        deck[drawn as usize] -= 1;
+        deck_len -= 1;
+        let pos = match policy {
+            RolloutPolicy::LeftToRight  => board[..len].iter().position(|&c| c == 0).unwrap_or(0),
+            RolloutPolicy::Proportional => proportional_slot(&st.rules, &board[..len], drawn),
+        };
+        board[pos] = drawn;
+        empties -= 1;
+    }
+    // スコア計算
+    st.rules.score_board(&board[..len])
+}
+
+/// 「比例配分」ヒューリスティック: 札 `v` が左右の最寄りの数札 L, R の間に収まる空きマスのうち、
+/// 区間内で (v − L) / (R − L) の位置に最も近いマスを選ぶ。収まる所が無ければ盤全体での
+/// 比例位置に最も近い空きマス。Joker は値の余裕 (R − L) / 空き数 が最も小さい区間の中央へ。
+/// `board` には空きマスが 1 つ以上あること
+fn proportional_slot(rules: &Rules, board: &[u8], card: u8) -> usize {
+    let n = board.len();
+    let (lo_v, hi_v) = (MIN_CARD as f64 - 1.0, MAX_CARD as f64 + 1.0);
+    // 左から: 直前の埋まりマスの位置と、左側で最寄りの数札
+    let mut left = [(-1isize, lo_v); MAX_BOARD];
+    let (mut at, mut val) = (-1isize, lo_v);
+    for (i, &c) in board.iter().enumerate() {
+        left[i] = (at, val);
+        if c != 0 { at = i as isize; }
+        if c != 0 && c != JOKER { val = c as f64; }
+    }
+    let mut right = [(n as isize, hi_v); MAX_BOARD];
+    let (mut at, mut val) = (n as isize, hi_v);
+    for (i, &c) in board.iter().enumerate().rev() {
+        right[i] = (at, val);
+        if c != 0 { at = i as isize; }
+        if c != 0 && c != JOKER { val = c as f64; }
+    }
+    let strict = rules.run_order == RunOrder::Increasing;
+    let v = card as f64;
+    let mut best = (f64::INFINITY, usize::MAX);
+    for (i, _) in board.iter().enumerate().filter(|&(_, &c)| c == 0) {
+        let ((a, lv), (b, rv)) = (left[i], right[i]);
+        let cost = if card == JOKER {
+            (rv - lv) / (b - a - 1) as f64 + ((i as isize - a) as f64 - (b - a) as f64 / 2.0).abs() * 1e-3
+        } else {
+            let fits = if strict { lv < v && v < rv } else { lv <= v && v <= rv };
+            if fits {
+                let target = a as f64 + (v - lv) / (rv - lv) * (b - a) as f64;
+                (i as f64 - target).abs()
+            } else {
+                // 収まらない: 盤全体の比例位置からの距離 (収まるマスより常に後回し)
+                let target = (v - lo_v) / (hi_v - lo_v) * (n as f64 - 1.0);
+                1e3 + (i as f64 - target).abs()
             }
-            // デッキ更新
-            deck[drawn as usize] -= 1;
-            deck_len -= 1;
-            board[pos] = drawn;
+        };
+        if cost < best.0 { best = (cost, i); }
+    }
+    best.1
+}
+
+/// 残り枚数に比例して 1 枚選ぶ (`deck_len` > 0)
+#[inline]
+fn sample_card(deck: &[u8; 32], deck_len: u8, rng: &mut SimpleRng) -> u8 {
+    // n 番目のカードを引く
+    let idx = rng.gen_range(deck_len);
+    let mut acc = 0u8;
+    for card in 1u8..=JOKER {
+        let c = deck[card as usize];
+        if acc + c > idx { return card; }
+        acc += c;
+    }
+    0
+}
+
+/*────────────── 得点分布 ─────────────*/
+/// 最終得点のヒストグラム。`counts()[i]` は得点 `min_score() + i` の回数で、
+/// 負の得点 (負の値を持つ得点表) が来るまでは min_score() == 0 (index == 得点)
+#[derive(Clone, Debug, Default)]
+pub struct ScoreDist { counts: Vec<u32>, min: i32, n: u32 }
+impl ScoreDist {
+    pub fn push(&mut self, score: i32) {
+        self.lower_to(score);
+        let s = (score - self.min) as usize;
+        if s >= self.counts.len() { self.counts.resize(s + 1, 0); }
+        self.counts[s] += 1;
+        self.n += 1;
+    }
+    /// 先頭を `score` まで広げる
+    fn lower_to(&mut self, score: i32) {
+        if score >= self.min { return; }
+        let k = (self.min - score) as usize;
+        self.counts.splice(0..0, core::iter::repeat_n(0, k));
+        self.min = score;
+    }
+    pub fn merge(&mut self, o: &ScoreDist) {
+        self.lower_to(o.min);
+        let off = (o.min - self.min) as usize;
+        if off + o.counts.len() > self.counts.len() { self.counts.resize(off + o.counts.len(), 0); }
+        for (a, &b) in self.counts[off..].iter_mut().zip(&o.counts) { *a += b; }
+        self.n += o.n;
+    }
+    #[inline] pub fn counts(&self) -> &[u32] { &self.counts }
+    /// `counts()[0]` の得点 (<= 0)
+    #[inline] pub fn min_score(&self) -> i32 { self.min }
+    #[inline] pub fn samples(&self) -> u32 { self.n }
+    pub fn mean(&self) -> f64 {
+        if self.n == 0 { return 0.0; }
+        self.counts.iter().enumerate().map(|(i, &c)| (self.min + i as i32) as f64 * c as f64).sum::<f64>() / self.n as f64
+    }
+    /// P(score >= target)
+    pub fn prob_at_least(&self, target: i32) -> f64 {
+        if self.n == 0 { return 0.0; }
+        let from = (target - self.min).max(0) as usize;
+        self.counts.iter().skip(from).map(|&c| c as u64).sum::<u64>() as f64 / self.n as f64
+    }
+    /// 累積割合が `q` に達する最小の得点
+    pub fn quantile(&self, q: f64) -> i32 {
+        let need = (q.clamp(0.0, 1.0) * self.n as f64).ceil().max(1.0) as u64;
+        let mut acc = 0u64;
+        for (i, &c) in self.counts.iter().enumerate() {
+            acc += c as u64;
+            if acc >= need { return self.min + i as i32; }
+        }
+        self.min + self.counts.len().saturating_sub(1) as i32
+    }
+}
+
+/// 現盤面から rollout 方策で `p.sims` 回最後まで打った最終得点の分布 (中断されたらそこまでの分)
+pub fn score_distribution(st: &GameState, p: &McParams, rng: &mut SimpleRng) -> ScoreDist {
+    let mut dist = ScoreDist::default();
+    if p.sims < 2 * ROLLOUT_CHUNK {
+        for i in 0..p.sims {
+            if i > 0 && i % 256 == 0 && p.cancelled() { break; }
+            dist.push(playout(st, p.rollout_policy, rng));
+        }
+        return dist;
+    }
+    let base = SimpleRng::new(rng.next_u64());
+    let parts = par_map(p.sims.div_ceil(ROLLOUT_CHUNK), |i| {
+        let mut rng = base.fork(i as u64);
+        let mut d = ScoreDist::default();
+        if i > 0 && p.cancelled() { return d; }
+        for _ in 0..ROLLOUT_CHUNK.min(p.sims - i * ROLLOUT_CHUNK) { d.push(playout(st, p.rollout_policy, &mut rng)); }
+        d
+    });
+    for d in &parts { dist.merge(d); }
+    dist
+}
+
+/// `card` を各空きマスに置いた後の最終得点分布
+pub fn score_distributions_after_card(st: &GameState, card: u8, p: &McParams, rng: &mut SimpleRng) -> Result<Vec<(usize, ScoreDist)>, StreamsError> {
+    let mut st = st.after_draw(card)?;
+    let empties: Vec<usize> = st.empty_positions().collect();
+    Ok(empties.into_iter().map(|pos| {
+        st.place(pos, card);
+        let d = score_distribution(&st, p, rng);
+        st.remove(pos);
+        (pos, d)
+    }).collect())
+}
+
+/*────────────── 着手推薦 ─────────────*/
+/// Welford 法による逐次平均・分散
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats { pub n: u32, pub mean: f64, m2: f64 }
+impl Stats {
+    /// 転送用: (n, mean, 偏差平方和) から復元
+    pub fn from_parts(n: u32, mean: f64, m2: f64) -> Self { Self { n, mean, m2 } }
+    /// 偏差平方和
+    #[inline] pub fn m2(&self) -> f64 { self.m2 }
+    #[inline] pub fn push(&mut self, x: f64) {
+        self.n += 1;
+        let d = x - self.mean;
+        self.mean += d / self.n as f64;
+        self.m2 += d * (x - self.mean);
+    }
+    /// 別系列の統計を合流 (Chan et al.)
+    pub fn merge(&mut self, o: &Stats) {
+        if o.n == 0 { return; }
+        let n = self.n + o.n;
+        let d = o.mean - self.mean;
+        self.mean += d * o.n as f64 / n as f64;
+        self.m2 += o.m2 + d * d * self.n as f64 * o.n as f64 / n as f64;
+        self.n = n;
+    }
+    /// 平均の標準誤差 (2 標本未満なら ∞)
+    pub fn stderr(&self) -> f64 {
+        if self.n < 2 { return f64::INFINITY; }
+        (self.m2 / (self.n - 1) as f64 / self.n as f64).sqrt()
+    }
+}
+
+/// 1 候補マスの評価結果
+#[derive(Clone, Copy, Debug)]
+pub struct MoveStat {
+    pub pos: usize,
+    pub mean: f64,
+    pub stderr: f64,
+    pub samples: u32,
+    /// 最善手の平均との差 (>= 0)
+    pub gap: f64,
+}
+
+/// 1 マスあたりの独立推定の本数。sims はこの本数に等分される
+pub const RECOMMEND_BATCHES: usize = 8;
+
+/// `card` を引いたとして各空きマスを独立バッチで評価し、平均の降順で返す。
+/// 期限付きならバッチ単位で打ち切る
+pub fn recommend(st: &GameState, card: u8, p: &McParams, rng: &mut SimpleRng) -> Result<Vec<MoveStat>, StreamsError> {
+    recommend_with_progress(st, card, p, rng, &mut |_| {})
+}
+
+/// `recommend` に途中経過の通知を付けたもの。バッチごとに
+/// `Progress { done, total: RECOMMEND_BATCHES, estimate: 暫定最善の平均 }` を渡す
+pub fn recommend_with_progress(st: &GameState, card: u8, p: &McParams, rng: &mut SimpleRng,
+                               on_progress: &mut dyn FnMut(&Progress)) -> Result<Vec<MoveStat>, StreamsError> {
+    Ok(ranked(&shard_stats(st, card, p, rng.next_u64(), 0, 1, on_progress)?))
+}
+
+/// `recommend` の分担分 (Web Worker などで分けて回す用)。バッチ b のうち
+/// b % shards == shard を担当し、マスごとの統計 (pos, Stats) を返す。各バッチの乱数は
+/// `seed` と b だけで決まるので、全シャードを `merge_shards` すれば分け方によらず同じ推定になる
+pub fn recommend_shard(st: &GameState, card: u8, p: &McParams, seed: u64, shard: usize, shards: usize)
+    -> Result<Vec<(usize, Stats)>, StreamsError> {
+    shard_stats(st, card, p, seed, shard, shards, &mut |_| {})
+}
+
+fn shard_stats(st: &GameState, card: u8, p: &McParams, seed: u64, shard: usize, shards: usize,
+               on_progress: &mut dyn FnMut(&Progress)) -> Result<Vec<(usize, Stats)>, StreamsError> {
+    let st = st.after_draw(card)?;
+    let q = McParams {
+        sims: p.sims.div_ceil(RECOMMEND_BATCHES).max(1),
+        iterations: p.iterations.div_ceil(RECOMMEND_BATCHES).max(1),
+        deadline: None,
+        ..*p
+    };
+    let empties: Vec<usize> = st.empty_positions().collect();
+    let mut stats = vec![Stats::default(); empties.len()];
+    // 終盤のマスは 1 つの置換表で 1 回だけ厳密に解き、全バッチでその値を使う
+    // (期限・中断で解けなかったマスは他と同じく Monte-Carlo)
+    let mut tt = TransTable::new(ENDGAME_TT_BYTES);
+    let mut work = st.clone();
+    let exact: Vec<Option<f64>> = empties.iter().map(|&pos| {
+        work.place(pos, card);
+        let v = endgame_exact(&mut work, p, &mut tt);
+        work.remove(pos);
+        v
+    }).collect();
+    let q = McParams { exact_threshold: 0, ..q };
+    let seed = SimpleRng::new(seed);
+    let shards = shards.max(1);
+    let total = RECOMMEND_BATCHES.saturating_sub(shard).div_ceil(shards);
+    for (k, batch) in (shard..RECOMMEND_BATCHES).step_by(shards).enumerate() {
+        // 標準誤差が出せる 2 バッチまでは期限を無視 (中断は 1 バッチ目の後から効く)
+        if (k >= 2 && p.stopped()) || (k >= 1 && p.cancelled()) { break; }
+        // マスごとに独立な乱数系列で (並列に) 評価
+        let base = seed.fork(batch as u64);
+        let vals = par_map(empties.len(), |i| {
+            if let Some(v) = exact[i] { return v; }
+            let mut st = st.clone();
+            st.place(empties[i], card);
+            evaluate(&mut st, &q, &mut base.fork(i as u64))
+        });
+        for (s, v) in stats.iter_mut().zip(vals) { s.push(v); }
+        let estimate = stats.iter().map(|s| s.mean).fold(f64::NEG_INFINITY, f64::max);
+        on_progress(&Progress { done: k + 1, total, estimate });
+    }
+    Ok(empties.into_iter().zip(stats).collect())
+}
+
+/// シャードごとの統計をマス単位で合流して `recommend` と同じ形にする
+pub fn merge_shards(parts: &[Vec<(usize, Stats)>]) -> Vec<MoveStat> {
+    let mut merged: Vec<(usize, Stats)> = Vec::new();
+    for &(pos, ref s) in parts.iter().flatten() {
+        match merged.iter_mut().find(|(p, _)| *p == pos) {
+            Some((_, m)) => m.merge(s),
+            None => merged.push((pos, *s)),
+        }
+    }
+    ranked(&merged)
+}
+
+/// マスごとの統計を平均の降順に並べ、最善との差を埋める
+fn ranked(stats: &[(usize, Stats)]) -> Vec<MoveStat> {
+    let mut moves: Vec<MoveStat> = stats.iter().map(|&(pos, s)| MoveStat {
+        pos, mean: s.mean, stderr: s.stderr(), samples: s.n, gap: 0.0,
+    }).collect();
+    sort_moves(&mut moves);
+    moves
+}
+
+/// 平均の降順に並べて gap を埋める
+fn sort_moves(moves: &mut [MoveStat]) {
+    moves.sort_by(|a, b| b.mean.total_cmp(&a.mean));
+    let best = moves.first().map_or(0.0, |m| m.mean);
+    for m in moves { m.gap = best - m.mean; }
+}
+
+/*────────────── 置換表 ─────────────*/
+pub const DEFAULT_TT_BYTES: usize = 64 << 20;
+
+#[derive(Clone, Copy, Default)]
+struct TtEntry { key: u64, val: f64 }
+
+/// 固定容量のハッシュ表 (常に上書き)。確保は最初の store まで遅延する
+pub struct TransTable {
+    slots: Vec<TtEntry>,
+    cap: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// ev_exact の枝刈り統計
+    pub prune: PruneStats,
+}
+impl TransTable {
+    /// `bytes` を超えない最大の 2 冪エントリ数で確保
+    pub fn new(bytes: usize) -> Self {
+        let n = (bytes / core::mem::size_of::<TtEntry>()).max(1);
+        let cap = 1usize << (usize::BITS - 1 - n.leading_zeros());
+        Self { slots: Vec::new(), cap, hits: 0, misses: 0, prune: PruneStats::default() }
+    }
+    #[inline] pub fn capacity(&self) -> usize { self.cap }
+    #[inline] pub fn get(&mut self, key: u64) -> Option<f64> {
+        match self.slots.get(key as usize & (self.cap - 1)) {
+            Some(e) if e.key == key => { self.hits += 1; Some(e.val) },
+            _                       => { self.misses += 1; None },
+        }
+    }
+    #[inline] pub fn put(&mut self, key: u64, val: f64) {
+        if self.slots.is_empty() { self.slots = vec![TtEntry::default(); self.cap]; }
+        self.slots[key as usize & (self.cap - 1)] = TtEntry { key, val };
+    }
+    pub fn clear(&mut self) { self.slots = Vec::new(); self.hits = 0; self.misses = 0; self.prune = PruneStats::default(); }
+}
+impl Default for TransTable { fn default() -> Self { Self::new(DEFAULT_TT_BYTES) } }
+
+/*────────────── Exact Expectimax ─────────────*/
+/// 置換表付きの厳密解。現実的なのは空き 5 マス程度まで (標準ルールの空き 5 マスで release 1.6 秒、
+/// 6 マス以上は分単位でも終わらない)。それより前は `evaluate` の Monte-Carlo を使う。
+/// 置換表は目的関数を区別しないので、目的を変えるときは clear() する
+pub fn ev_exact(st: &mut GameState, obj: &Objective, tt: &mut TransTable) -> f64 {
+    exact(st, obj, tt, &McParams::default()).expect("no deadline")
+}
+
+/// 空きマスが `p.exact_threshold` 未満なら `p` の期限・中断付きで厳密に解く
+/// (対象外か打ち切りなら None)
+fn endgame_exact(st: &mut GameState, p: &McParams, tt: &mut TransTable) -> Option<f64> {
+    if st.empty_positions().count() >= p.exact_threshold { return None; }
+    exact(st, &p.objective, tt, p)
+}
+
+/// `ev_exact` の本体。1024 展開ごとに `p` の期限と中断を見て、打ち切ったら None
+/// (未完了の局面は置換表に入れないので、同じ表で続きを解き直せる)
+fn exact(st: &mut GameState, obj: &Objective, tt: &mut TransTable, p: &McParams) -> Option<f64> {
+    if st.deck_len == 0 || st.empty_positions().next().is_none() {
+        return Some(obj.utility(st.score()));
+    }
+    let key = st.key();
+    if let Some(v) = tt.get(key) { return Some(v); }
+    if tt.prune.expanded.is_multiple_of(1024) && p.stopped() { return None; }
+    let mut ev = 0.0f64;
+    let deck_len_f = st.deck_len as f64;
+    // 最後の 1 マスは札の同値類で厳密にまとめられる
+    let last = st.empty_positions().nth(1).is_none();
+    let (draws, n) = if last { card_classes(st) } else { card_counts(st) };
+    for &(card, cnt) in &draws[..n] {
+        st.draw(card);
+        let mut best = f64::NEG_INFINITY;
+        let (moves, m) = ordered_moves(st, card, obj, true);
+        for (k, &(ub, pos)) in moves[..m].iter().enumerate() {
+            if ub <= best { tt.prune.pruned += (m - k) as u64; break; }
+            tt.prune.expanded += 1;
+            st.place(pos, card);
+            let v = exact(st, obj, tt, p);
+            st.remove(pos);
+            let Some(v) = v else { st.undraw(card); return None };
+            best = best.max(v);
         }
-        // スコア計算
-        let gs_sim = GameState { board, deck_count: deck, deck_len };
-        sum += gs_sim.score() as f64;
+        ev += (cnt as f64 / deck_len_f) * best;
+        st.undraw(card);
     }
-    sum / p.sims as f64
+    tt.put(key, ev);
+    Some(ev)
 }
 
 /*────────────── 盤面文字列変換 ─────────────*/
 #[inline]
-pub fn board_from_str(s: &str) -> Result<[u8; BOARD_SIZE], String> {
-    if s.chars().count() != BOARD_SIZE { return Err("board string must be 20 chars".into()); }
+pub fn board_from_str(s: &str) -> Result<[u8; BOARD_SIZE], StreamsError> {
+    let v = board_from_str_len(s, BOARD_SIZE)?;
     let mut arr = [0u8; BOARD_SIZE];
+    arr.copy_from_slice(&v);
+    Ok(arr)
+}
+
+/// 任意長 (`Rules::board_len`) の盤面文字列
+pub fn board_from_str_len(s: &str, len: usize) -> Result<Vec<u8>, StreamsError> {
+    let found = s.chars().count();
+    if found != len { return Err(StreamsError::BadLength { expected: len, found }); }
+    s.chars().enumerate().map(|(i, ch)| match ch {
+        '_' | '0' => Ok(0),
+        _         => card_from_char(ch).ok_or(StreamsError::BadChar { pos: i, ch }),
+    }).collect()
+}
+
+/// 残り山札の文字列 (盤面と同じ 1 文字 1 枚、順不同。例: "BBC★")
+pub fn deck_from_str(s: &str) -> Result<[u8; 32], StreamsError> {
+    let mut deck = [0u8; 32];
     for (i, ch) in s.chars().enumerate() {
-        arr[i] = match ch {
-            '_' => 0,
-            '★' => JOKER,
-            '0'..='9' => ch.to_digit(10).unwrap() as u8,
-            'A'..='U' => 10 + (ch as u8 - b'A'),
-            _ => return Err(format!("bad char {ch}")),
-        };
+        let card = card_from_char(ch).ok_or(StreamsError::BadChar { pos: i, ch })?;
+        deck[card as usize] = deck[card as usize].saturating_add(1);
+    }
+    Ok(deck)
+}
+
+/// 札の 1 文字表記 (盤面文字列と同じ)
+pub fn card_to_char(card: u8) -> Option<char> {
+    match card {
+        JOKER   => Some('★'),
+        1..=9   => Some((b'0' + card) as char),
+        10..=30 => Some((b'A' + card - 10) as char),
+        _ => None,
+    }
+}
+
+#[inline]
+fn card_from_char(ch: char) -> Option<u8> {
+    match ch {
+        '★' => Some(JOKER),
+        '1'..='9' => Some(ch as u8 - b'0'),
+        'A'..='U' => Some(10 + (ch as u8 - b'A')),
+        _ => None,
     }
-    Ok(arr)
 }
 
 /*────────────── Wasm エクスポート ─────────────*/
+/// `Error` に `kind` と問題箇所 (`pos` / `ch` / `card` / `expected` / `found`) を載せて返す
 #[cfg(target_arch = "wasm32")]
+impl From<StreamsError> for JsValue {
+    fn from(e: StreamsError) -> Self {
+        let err = js_sys::Error::new(&e.to_string());
+        let (kind, fields): (&str, Vec<(&str, JsValue)>) = match e {
+            StreamsError::BadLength { expected, found } =>
+                ("BadLength", vec![("expected", (expected as u32).into()), ("found", (found as u32).into())]),
+            StreamsError::BadChar { pos, ch } =>
+                ("BadChar", vec![("pos", (pos as u32).into()), ("ch", ch.to_string().into())]),
+            StreamsError::OverusedCard { card, pos } =>
+                ("OverusedCard", vec![("card", card.into()), ("pos", pos.map(|p| p as u32).into())]),
+            StreamsError::FullBoard => ("FullBoard", vec![]),
+            StreamsError::DeckMismatch { player } => ("DeckMismatch", vec![("player", (player as u32).into())]),
+            StreamsError::BadPosition { pos } => ("BadPosition", vec![("pos", (pos as u32).into())]),
+            StreamsError::BadDeck { total, slot0 } => ("BadDeck", vec![("total", (total as u32).into()), ("slot0", slot0.into())]),
+        };
+        let _ = js_sys::Reflect::set(&err, &"kind".into(), &kind.into());
+        for (k, v) in fields {
+            let _ = js_sys::Reflect::set(&err, &k.into(), &v);
+        }
+        err.into()
+    }
+}
+
+/// `deck` を省略すると「全札 − 盤上の札」を山札とみなす
+#[cfg(target_arch = "wasm32")]
+fn parse_state(board: &str, deck: Option<String>) -> Result<GameState, StreamsError> {
+    let board = board_from_str(board)?;
+    match deck {
+        Some(d) => GameState::with_deck(Rules::default(), &board, deck_from_str(&d)?),
+        None    => GameState::new(board),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn budget_deadline(budget_ms: Option<f64>) -> Option<Deadline> {
+    budget_ms.map(|ms| Deadline::after(Duration::from_secs_f64(ms.max(0.0) / 1e3)))
+}
+
+/// "mcts" なら UCT、それ以外 (省略含む) は Hybrid
+#[cfg(target_arch = "wasm32")]
+fn parse_algorithm(algo: Option<String>) -> Algorithm {
+    match algo.as_deref() {
+        Some("mcts") => Algorithm::Mcts,
+        _            => Algorithm::Hybrid,
+    }
+}
+
+/// 中断ハンドル `abort` は `aborted` を持つ任意のオブジェクト (`AbortController().signal` など)。
+/// Wasm は単一スレッドなので探索中に値が変わるのは `progress` コールバックの中だけで、
+/// 確認も開始時とコールバックの直後だけ行う (外から止めるなら Worker ごと terminate する)
+#[cfg(target_arch = "wasm32")]
+fn js_aborted(abort: Option<&js_sys::Object>) -> bool {
+    abort.is_some_and(|a| js_sys::Reflect::get(a, &"aborted".into()).is_ok_and(|v| v.is_truthy()))
+}
+
+/// `progress(done, total, estimate)` を呼ぶ通知関数。コールバックが `false` を返すか例外を投げるか、
+/// `abort.aborted` が立ったら `flag` を立てる
+#[cfg(target_arch = "wasm32")]
+fn js_progress<'a>(progress: Option<&'a js_sys::Function>, abort: Option<&'a js_sys::Object>, flag: &'a AtomicBool) -> impl FnMut(&Progress) + 'a {
+    move |pr| {
+        let stop = progress.is_some_and(|f| {
+            f.call3(&JsValue::NULL, &JsValue::from(pr.done as u32), &JsValue::from(pr.total as u32), &JsValue::from(pr.estimate))
+                .map_or(true, |v| v.as_bool() == Some(false))
+        });
+        if stop || js_aborted(abort) { flag.store(true, Ordering::Relaxed); }
+    }
+}
+
+/// `progress(done, total, estimate)` は任意の途中経過コールバック (`false` を返すと中断)、
+/// `abort` は任意の中断ハンドル
+#[cfg(target_arch = "wasm32")]
+#[allow(clippy::too_many_arguments)]
 #[wasm_bindgen]
-pub fn expected_value_current_board(board: &str, sims: usize) -> f64 {
-    let board = board_from_str(board).expect("bad board");
-    let mut st = GameState::new(board);
-    let p = McParams { sims, ..Default::default() };
+pub fn expected_value_current_board(board: &str, sims: usize, budget_ms: Option<f64>, deck: Option<String>, algo: Option<String>,
+                                    progress: Option<js_sys::Function>, abort: Option<js_sys::Object>) -> Result<f64, JsValue> {
+    let mut st = parse_state(board, deck)?;
+    let flag = AtomicBool::new(js_aborted(abort.as_ref()));
+    let p = McParams { sims, deadline: budget_deadline(budget_ms), cancel: Some(&flag), algorithm: parse_algorithm(algo), ..Default::default() };
     let mut rng = SimpleRng::default();
-    ev_before_draw(&mut st, &p, &mut rng, 0)
+    Ok(evaluate_with_progress(&mut st, &p, &mut rng, &mut js_progress(progress.as_ref(), abort.as_ref(), &flag)).value)
+}
+
+/// マスごとに評価し、1 マス終わるたびに `progress(done, total, ev)` を呼ぶ。中断されたら残りのマスは 0
+#[cfg(target_arch = "wasm32")]
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen]
+pub fn expected_values_after_card(board: &str, card: u8, sims: usize, budget_ms: Option<f64>, deck: Option<String>, algo: Option<String>,
+                                  progress: Option<js_sys::Function>, abort: Option<js_sys::Object>) -> Result<Float64Array, JsValue> {
+    let st = parse_state(board, deck)?;
+    let flag = AtomicBool::new(js_aborted(abort.as_ref()));
+    let p = McParams { sims, deadline: budget_deadline(budget_ms), cancel: Some(&flag), algorithm: parse_algorithm(algo), ..Default::default() };
+    let vals = evaluate_after_card(&st, card, &p, &mut SimpleRng::default(), &mut js_progress(progress.as_ref(), abort.as_ref(), &flag))?;
+    Ok(Float64Array::from(&vals[..]))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn js_obj(fields: &[(&str, JsValue)]) -> JsValue {
+    let o = js_sys::Object::new();
+    for (k, v) in fields {
+        let _ = js_sys::Reflect::set(&o, &JsValue::from_str(k), v);
+    }
+    o.into()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn dist_to_js(d: &ScoreDist) -> JsValue {
+    js_obj(&[
+        ("counts",  js_sys::Uint32Array::from(d.counts()).into()),
+        ("min",     JsValue::from(d.min_score())),
+        ("samples", JsValue::from(d.samples())),
+        ("mean",    JsValue::from(d.mean())),
+        ("p10",     JsValue::from(d.quantile(0.1))),
+        ("p50",     JsValue::from(d.quantile(0.5))),
+        ("p90",     JsValue::from(d.quantile(0.9))),
+    ])
+}
+
+/// 現盤面の最終得点分布 `{counts, min, samples, mean, p10, p50, p90}` (counts[i] は得点 min + i)
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn score_histogram(board: &str, sims: usize, deck: Option<String>) -> Result<JsValue, JsValue> {
+    let st = parse_state(board, deck)?;
+    let p = McParams { sims, ..Default::default() };
+    Ok(dist_to_js(&score_distribution(&st, &p, &mut SimpleRng::default())))
 }
 
+/// `card` を置く各マスについて `{pos, dist}` の配列
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
-pub fn expected_values_after_card(board: &str, card: u8, sims: usize) -> Float64Array {
-    let board_arr = board_from_str(board).expect("bad board");
-    let mut st = GameState::new(board_arr);
+pub fn score_histograms_after_card(board: &str, card: u8, sims: usize, deck: Option<String>) -> Result<js_sys::Array, JsValue> {
+    let st = parse_state(board, deck)?;
     let p = McParams { sims, ..Default::default() };
+    Ok(score_distributions_after_card(&st, card, &p, &mut SimpleRng::default())?.iter()
+        .map(|(pos, d)| js_obj(&[("pos", JsValue::from(*pos as u32)), ("dist", dist_to_js(d))]))
+        .collect())
+}
+
+/// 候補マスを平均の降順で `{pos, mean, stderr, samples, gap}` の配列として返す。
+/// `progress(done, total, best)` はバッチごとに呼ばれる
+#[cfg(target_arch = "wasm32")]
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen(js_name = recommend)]
+pub fn recommend_js(board: &str, card: u8, sims: usize, budget_ms: Option<f64>, deck: Option<String>, algo: Option<String>,
+                    progress: Option<js_sys::Function>, abort: Option<js_sys::Object>) -> Result<js_sys::Array, JsValue> {
+    let st = parse_state(board, deck)?;
+    let flag = AtomicBool::new(js_aborted(abort.as_ref()));
+    let p = McParams { sims, deadline: budget_deadline(budget_ms), cancel: Some(&flag), algorithm: parse_algorithm(algo), ..Default::default() };
     let mut rng = SimpleRng::default();
-    let mut vals = [0.0f64; BOARD_SIZE];
-    for pos in st.clone().empty_positions() {
-        st.place(pos, card);
-        vals[pos] = ev_before_draw(&mut st, &p, &mut rng, 0);
-        st.remove(pos);
+    Ok(recommend_with_progress(&st, card, &p, &mut rng, &mut js_progress(progress.as_ref(), abort.as_ref(), &flag))?.iter().map(move_to_js).collect())
+}
+
+/// Worker 用: `recommend` のシャード `shard` / `shards` を `{pos, n, mean, m2}` の配列で返す。
+/// 全 Worker に同じ `seed` を渡し、結果を `merge_recommend` に集める
+#[cfg(target_arch = "wasm32")]
+#[allow(clippy::too_many_arguments)]
+#[wasm_bindgen(js_name = recommendShard)]
+pub fn recommend_shard_js(board: &str, card: u8, sims: usize, seed: u32, shard: usize, shards: usize,
+                          budget_ms: Option<f64>, deck: Option<String>, algo: Option<String>) -> Result<js_sys::Array, JsValue> {
+    let st = parse_state(board, deck)?;
+    let p = McParams { sims, deadline: budget_deadline(budget_ms), algorithm: parse_algorithm(algo), ..Default::default() };
+    Ok(recommend_shard(&st, card, &p, seed as u64, shard, shards)?.iter().map(|(pos, s)| js_obj(&[
+        ("pos",  JsValue::from(*pos as u32)),
+        ("n",    JsValue::from(s.n)),
+        ("mean", JsValue::from(s.mean)),
+        ("m2",   JsValue::from(s.m2())),
+    ])).collect())
+}
+
+/// `recommendShard` の結果の配列 (Worker ごと) を合流し、`recommend` と同じ形で返す
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = mergeRecommend)]
+pub fn merge_recommend_js(parts: js_sys::Array) -> js_sys::Array {
+    let num = |o: &JsValue, k: &str| js_sys::Reflect::get(o, &k.into()).ok().and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let parts: Vec<Vec<(usize, Stats)>> = parts.iter().map(|part| {
+        js_sys::Array::from(&part).iter()
+            .map(|o| (num(&o, "pos") as usize, Stats::from_parts(num(&o, "n") as u32, num(&o, "mean"), num(&o, "m2"))))
+            .collect()
+    }).collect();
+    merge_shards(&parts).iter().map(move_to_js).collect()
+}
+
+/// 1 局を通して持つソルバ。手番をまたいで推定値と置換表を使い回す
+/// ```js
+/// const s = new Solver(board, 20);        // (board, sims, deck?, algo?)
+/// s.valuesAfterCard(card, 200);          // [{pos, mean, stderr, samples, gap}]  呼ぶほど精度が上がる
+/// s.place(card, pos); s.undo();
+/// ```
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = Solver)]
+pub struct JsSolver { inner: Solver }
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_class = Solver)]
+impl JsSolver {
+    #[wasm_bindgen(constructor)]
+    pub fn new(board: &str, sims: usize, deck: Option<String>, algo: Option<String>) -> Result<JsSolver, JsValue> {
+        let p = McParams { sims, algorithm: parse_algorithm(algo), ..Default::default() };
+        Ok(Self { inner: Solver::new(parse_state(board, deck)?, p, js_sys::Date::now() as u64) })
     }
-    Float64Array::from(&vals[..])
+
+    pub fn place(&mut self, card: u8, pos: usize) -> Result<(), JsValue> { Ok(self.inner.place(card, pos)?) }
+
+    /// 取り消した手 `{card, pos}` (手順が空なら undefined)
+    pub fn undo(&mut self) -> JsValue {
+        self.inner.undo().map_or(JsValue::UNDEFINED, |m| js_obj(&[("card", m.card.into()), ("pos", (m.pos as u32).into())]))
+    }
+
+    /// 現局面の推定値 `{mean, stderr, samples, exact}`
+    #[wasm_bindgen(js_name = expectedValue)]
+    pub fn expected_value(&mut self, budget_ms: Option<f64>) -> JsValue {
+        let e = self.inner.value(budget_ms.map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1e3)));
+        js_obj(&[
+            ("mean",    JsValue::from(e.mean)),
+            ("stderr",  JsValue::from(e.stderr)),
+            ("samples", JsValue::from(e.samples)),
+            ("exact",   JsValue::from(e.exact)),
+        ])
+    }
+
+    /// `recommend` と同じ形の配列
+    #[wasm_bindgen(js_name = valuesAfterCard)]
+    pub fn values_after_card(&mut self, card: u8, budget_ms: Option<f64>) -> Result<js_sys::Array, JsValue> {
+        let moves = self.inner.values_after_card(card, budget_ms.map(|ms| Duration::from_secs_f64(ms.max(0.0) / 1e3)))?;
+        Ok(moves.iter().map(move_to_js).collect())
+    }
+
+    /// `{positions, samples, reused, ttHits}`
+    pub fn stats(&self) -> JsValue {
+        let s = self.inner.stats();
+        js_obj(&[
+            ("positions", JsValue::from(s.positions as u32)),
+            ("samples",   JsValue::from(s.samples as f64)),
+            ("reused",    JsValue::from(s.reused as f64)),
+            ("ttHits",    JsValue::from(s.tt_hits as f64)),
+        ])
+    }
+
+    /// キャッシュを捨てる (局面と手順はそのまま)
+    pub fn clear(&mut self) { self.inner.clear(); }
+
+    /// `step` で評価する札。省略で解除
+    #[wasm_bindgen(js_name = setCard)]
+    pub fn set_card(&mut self, card: Option<u8>) -> Result<(), JsValue> { Ok(self.inner.set_card(card)?) }
+
+    /// rollout を `n_rollouts` 本足して、マスごとの推定を `recommend` と同じ形で返す
+    /// (95% 信頼幅は mean ± 1.96·stderr)。requestAnimationFrame から少しずつ呼ぶ:
+    /// ```js
+    /// s.setCard(card);
+    /// const tick = () => { draw(s.step(300)); if (s.card !== undefined) requestAnimationFrame(tick); };
+    /// ```
+    pub fn step(&mut self, n_rollouts: usize) -> js_sys::Array {
+        self.inner.step(n_rollouts).iter().map(move_to_js).collect()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn card(&self) -> Option<u8> { self.inner.card() }
+}
+
+/// 札 × マスの評価表 `{values, stderr, best, samples}`。values / stderr は行優先
+/// (`(card - 1) * 20 + pos`、Joker = 31) の Float64Array で、山札に無い札と埋まったマスは NaN。
+/// best は札ごとの最善マス (無ければ -1) の Int32Array
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = evMatrix)]
+pub fn ev_matrix_js(board: &str, sims: usize, budget_ms: Option<f64>, deck: Option<String>) -> Result<JsValue, JsValue> {
+    let st = parse_state(board, deck)?;
+    let p = McParams { sims, deadline: budget_deadline(budget_ms), ..Default::default() };
+    let m = ev_matrix(&st, &p, &mut SimpleRng::default());
+    let cells = |f: &dyn Fn(u8, usize) -> f64| -> Vec<f64> {
+        (1..=JOKER).flat_map(|c| (0..m.board_len).map(move |pos| (c, pos))).map(|(c, pos)| f(c, pos)).collect()
+    };
+    let values = cells(&|c, pos| m.mean(c, pos));
+    let stderr = cells(&|c, pos| if m.mean(c, pos).is_nan() { f64::NAN } else { m.stderr(c, pos) });
+    let best: Vec<i32> = (1..=JOKER).map(|c| m.best(c).map_or(-1, |pos| pos as i32)).collect();
+    Ok(js_obj(&[
+        ("values",  Float64Array::from(&values[..]).into()),
+        ("stderr",  Float64Array::from(&stderr[..]).into()),
+        ("best",    js_sys::Int32Array::from(&best[..]).into()),
+        ("samples", JsValue::from(m.samples as u32)),
+    ]))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn move_to_js(m: &MoveStat) -> JsValue {
+    js_obj(&[
+        ("pos",     JsValue::from(m.pos as u32)),
+        ("mean",    JsValue::from(m.mean)),
+        ("stderr",  JsValue::from(m.stderr)),
+        ("samples", JsValue::from(m.samples)),
+        ("gap",     JsValue::from(m.gap)),
+    ])
 }
 
 /*─────────────────────────────── Tests (native) ───────────────────────────────*/
@@ -243,18 +1490,616 @@ mod tests {
     #[test]
     fn basic_score() {
         let b = board_from_str("123456789ABCDEFGHI__").unwrap();
-        let gs = GameState::new(b);
+        let gs = GameState::new(b).unwrap();
         assert_eq!(gs.score(), 100); // 20 連 run == 300 点
     }
 
+    /// Joker を 1‒30 の全値で置き換えた最大得点 (総当たり)
+    fn brute_joker_score(rules: &Rules, board: &[u8]) -> i32 {
+        match board.iter().position(|&c| c == JOKER) {
+            None => rules.score_board(board),
+            Some(j) => (MIN_CARD..=MAX_CARD).map(|v| {
+                let mut b = board.to_vec();
+                b[j] = v;
+                brute_joker_score(rules, &b)
+            }).max().unwrap(),
+        }
+    }
+
+    #[test]
+    fn joker_starts_and_bridges_runs() {
+        let rules = Rules::default();
+        // 先頭の Joker も run の始まりになる
+        assert_eq!(GameState::new(board_from_str("★23456789ABCDEFGHIJK").unwrap()).unwrap().score(), 300);
+        // 空きマス直後の Joker
+        assert_eq!(rules.score_board(&[1, 2, 0, JOKER, 5, 6]), 1 + 3);
+        // 単独の Joker は長さ 1 の run
+        assert_eq!(rules.score_board(&[0, JOKER, 0]), 0);
+        // 降順の間: どちらの run にも付けられる
+        assert_eq!(rules.score_board(&[7, 8, 9, JOKER, 3, 4]), 5 + 1);
+        // 昇順の全盤面で Joker をどこに置いても 20 連
+        for j in 0..BOARD_SIZE {
+            let mut b: Vec<u8> = (1..=BOARD_SIZE as u8).collect();
+            b[j] = JOKER;
+            assert_eq!(rules.score_board(&b), 300, "joker at {j}");
+        }
+    }
+
+    #[test]
+    fn joker_matches_bruteforce() {
+        let mut rng = SimpleRng::new(42);
+        let mut two_jokers = Rules::default();
+        two_jokers.deck[JOKER as usize] = 2;
+        for rules in [Rules::default(), two_jokers, Rules { run_order: RunOrder::Increasing, ..two_jokers }] {
+            for _ in 0..300 {
+                let jokers = rules.deck[JOKER as usize];
+                let mut b: Vec<u8> = (0..12).map(|_| match rng.gen_range(10) {
+                    0     => 0,
+                    1..=3 => 1 + rng.gen_range(6),
+                    _     => 1 + rng.gen_range(MAX_CARD),
+                }).collect();
+                for _ in 0..jokers { b[rng.gen_range(12) as usize] = JOKER; }
+                assert_eq!(rules.score_board(&b), brute_joker_score(&rules, &b), "{b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_boards_are_reported() {
+        assert_eq!(board_from_str("123").unwrap_err(), StreamsError::BadLength { expected: 20, found: 3 });
+        assert_eq!(board_from_str("1234567890ABCDEFGHI?").unwrap_err(), StreamsError::BadChar { pos: 19, ch: '?' });
+        let two_fives = board_from_str("5_5_________________").unwrap();
+        assert_eq!(GameState::new(two_fives).unwrap_err(), StreamsError::OverusedCard { card: 5, pos: Some(2) });
+        let two_jokers = board_from_str("★________________★__").unwrap();
+        assert_eq!(GameState::new(two_jokers).unwrap_err(), StreamsError::OverusedCard { card: JOKER, pos: Some(17) });
+        let full = GameState::new(board_from_str("123456789ABCDEFGHIJK").unwrap()).unwrap();
+        let p = McParams::default();
+        assert_eq!(recommend(&full, 30, &p, &mut SimpleRng::new(1)).unwrap_err(), StreamsError::FullBoard);
+    }
+
+    #[test]
+    fn explicit_deck() {
+        let board = board_from_str("123456789ABCDEFGHIJ_").unwrap();
+        // 山札が 20 だけなら必ず 300 点
+        let st = GameState::with_deck(Rules::default(), &board, deck_from_str("K").unwrap()).unwrap();
+        assert_eq!(st.deck_len(), 1);
+        let mut st2 = st.clone();
+        assert_eq!(ev_before_draw(&mut st2, &McParams::default(), &mut SimpleRng::new(1), 0), 300.0);
+        // 盤上の 5 と合わせて 2 枚目の 5 はルール違反
+        assert_eq!(GameState::with_deck(Rules::default(), &board, deck_from_str("5K").unwrap()).unwrap_err(),
+                   StreamsError::OverusedCard { card: 5, pos: None });
+        // 11 は 2 枚あるので盤上 1 枚 + 山札 1 枚は可
+        assert!(GameState::with_deck(Rules::default(), &board, deck_from_str("BK★").unwrap()).is_ok());
+        assert_eq!(deck_from_str("B_").unwrap_err(), StreamsError::BadChar { pos: 1, ch: '_' });
+    }
+
     #[test]
     fn ev_two_empty_cells_mc_vs_bruteforce() {
         let board_str = "123456789ABCDEFGHI__";
         let board  = board_from_str(board_str).unwrap();
-        let mut state  = GameState::new(board);
-        let params = McParams { sims: 5000, rollout_limit: 2 };
+        let mut state  = GameState::new(board).unwrap();
+        let params = McParams { sims: 5000, rollout_limit: 2, ..Default::default() };
         let mut rng = SimpleRng::default();
         let mc_ev = ev_before_draw(&mut state, &params, &mut rng, 0);
         println!("EV (MC) = {:.3}", mc_ev);
     }
+
+    #[test]
+    fn exact_matches_full_expansion() {
+        // rollout_limit >= 空きマス数なら ev_before_draw も厳密解
+        let board = board_from_str("1234_6789AB_DEFGHI_K").unwrap();
+        let mut state = GameState::new(board).unwrap();
+        let params = McParams { sims: 1, rollout_limit: 3, ..Default::default() };
+        let mut rng = SimpleRng::new(1);
+        let full = ev_before_draw(&mut state, &params, &mut rng, 0);
+        let mut tt = TransTable::new(1 << 20);
+        let exact = ev_exact(&mut state, &Objective::Mean, &mut tt);
+        assert!((full - exact).abs() < 1e-9, "{full} vs {exact}");
+        assert!(tt.hits > 0);
+    }
+
+    #[test]
+    fn recommend_sorted_with_gaps() {
+        let board = board_from_str("123456789ABCDEFGH___").unwrap();
+        let state = GameState::new(board).unwrap();
+        let params = McParams { sims: 64, ..Default::default() };
+        let mut rng = SimpleRng::new(3);
+        let moves = recommend(&state, 30, &params, &mut rng).unwrap();
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[0].gap, 0.0);
+        assert!(moves.windows(2).all(|w| w[0].mean >= w[1].mean));
+        assert!(moves.iter().all(|m| m.samples == RECOMMEND_BATCHES as u32 && m.stderr.is_finite()));
+        // 山札に無いカードはエラー
+        assert_eq!(recommend(&state, 1, &params, &mut rng).unwrap_err(),
+                   StreamsError::OverusedCard { card: 1, pos: None });
+    }
+
+    #[test]
+    fn distribution_matches_rollout_mean() {
+        let board = board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap();
+        let state = GameState::new(board).unwrap();
+        let params = McParams { sims: 500, ..Default::default() };
+        let dist = score_distribution(&state, &params, &mut SimpleRng::new(11));
+        let mean = rollout(&state, &params, &mut SimpleRng::new(11));
+        assert_eq!(dist.samples(), 500);
+        assert!((dist.mean() - mean).abs() < 1e-9);
+        assert!(dist.quantile(0.1) <= dist.quantile(0.5) && dist.quantile(0.5) <= dist.quantile(0.9));
+        assert_eq!(dist.prob_at_least(0), 1.0);
+        // 埋まった盤面は一点分布
+        let full = GameState::new(board_from_str("123456789ABCDEFGHIJK").unwrap()).unwrap();
+        let d = score_distribution(&full, &params, &mut SimpleRng::new(1));
+        assert_eq!(d.quantile(0.0), full.score());
+        assert_eq!(d.quantile(1.0), full.score());
+        // 負の得点は 0 に丸めず、先頭を広げて数える
+        let mut neg = ScoreDist::default();
+        for x in [3, -2, 0, -2] { neg.push(x); }
+        assert_eq!((neg.min_score(), neg.counts()), (-2, &[2, 0, 1, 0, 0, 1][..]));
+        assert_eq!(neg.mean(), -0.25);
+        assert_eq!((neg.quantile(0.5), neg.quantile(1.0)), (-2, 3));
+        assert_eq!(neg.prob_at_least(-1), 0.5);
+        let mut m = ScoreDist::default();
+        m.push(1);
+        m.merge(&neg);
+        assert_eq!((m.min_score(), m.counts(), m.samples()), (-2, &[2, 0, 1, 1, 0, 1][..], 5));
+    }
+
+    #[test]
+    fn objectives_on_last_cell() {
+        // 残り 21 枚のうち 19,20‒30,★ の 13 枚なら 20 連で 300 点
+        let board = board_from_str("123456789ABCDEFGHIJ_").unwrap();
+        let mut state = GameState::new(board).unwrap();
+        let mut rng = SimpleRng::new(5);
+        let p = McParams { objective: Objective::AtLeast(300), ..Default::default() };
+        let prob = ev_before_draw(&mut state, &p, &mut rng, 0);
+        assert!((prob - 13.0 / 21.0).abs() < 1e-12);
+        let mean = ev_before_draw(&mut state, &McParams::default(), &mut rng, 0);
+        let objective = Objective::cvar(0.2, &state, &McParams { sims: 200, ..Default::default() }, &mut rng);
+        let cvar = ev_exact(&mut state, &objective, &mut TransTable::new(1 << 16));
+        assert!(cvar <= mean + 1e-9);
+        let util = Objective::Utility(|s| (s as f64).sqrt());
+        let u = ev_before_draw(&mut state, &McParams { objective: util, ..Default::default() }, &mut rng, 0);
+        assert!(u <= mean.sqrt() + 1e-9); // Jensen
+    }
+
+    #[test]
+    fn custom_rules_small_game() {
+        let mut deck = [0u8; 32];
+        deck[1..=8].fill(1);
+        deck[2] = 2;
+        let mut score_table = [0i32; MAX_BOARD + 1];
+        score_table[..5].copy_from_slice(&[0, 0, 1, 3, 10]);
+        let strict = Rules { board_len: 4, deck, score_table, run_order: RunOrder::Increasing };
+        let loose  = Rules { run_order: RunOrder::NonDecreasing, ..strict };
+        assert_eq!(strict.score_board(&[1, 2, 2, 3]), 2);
+        assert_eq!(loose.score_board(&[1, 2, 2, 3]), 10);
+        // 小さな盤でも厳密解 == 全展開
+        let mut state = GameState::with_rules(strict, &[0, 4, 0, 0]).unwrap();
+        assert_eq!(state.deck_len, 8);
+        let params = McParams { sims: 1, rollout_limit: 3, ..Default::default() };
+        let full = ev_before_draw(&mut state, &params, &mut SimpleRng::new(1), 0);
+        let exact = ev_exact(&mut state, &Objective::Mean, &mut TransTable::new(1 << 12));
+        assert!((full - exact).abs() < 1e-9);
+        // 255 枚を超える山札と index 0 の札は拒否
+        let mut big = strict;
+        big.deck[1..=31].fill(9);
+        assert_eq!(GameState::with_rules(big, &[0; 4]).unwrap_err(), StreamsError::BadDeck { total: 279, slot0: 0 });
+        big.deck[1..=31].fill(8);
+        assert_eq!(GameState::with_rules(big, &[0; 4]).unwrap().deck_len(), 248);
+        let mut zero = strict;
+        zero.deck[0] = 1;
+        assert_eq!(GameState::with_rules(zero, &[0; 4]).unwrap_err(), StreamsError::BadDeck { total: 10, slot0: 1 });
+    }
+
+    #[test]
+    fn mcts_close_to_exact() {
+        let board = board_from_str("123456789ABCDEF_H_J_").unwrap();
+        let mut state = GameState::new(board).unwrap();
+        let exact = ev_exact(&mut state, &Objective::Mean, &mut TransTable::new(1 << 16));
+        let p = McParams { algorithm: Algorithm::Mcts, iterations: 20_000, exact_threshold: 0, ..Default::default() };
+        let mcts = evaluate(&mut state, &p, &mut SimpleRng::new(9));
+        assert!((mcts - exact).abs() < 0.1 * exact, "mcts {mcts} vs exact {exact}");
+        // 盤面は元に戻っている
+        assert_eq!(state.board(), &board[..]);
+    }
+
+    #[test]
+    fn endgame_switches_to_exact() {
+        let mut state = GameState::new(board_from_str("123456789ABCDEFGH___").unwrap()).unwrap();
+        let exact = ev_exact(&mut state, &Objective::Mean, &mut TransTable::new(1 << 16));
+        let p = McParams { exact_threshold: 4, ..Default::default() };
+        let e = evaluate_with_mode(&mut state, &p, &mut SimpleRng::new(1));
+        assert_eq!(e.mode, SolveMode::Exact);
+        assert_eq!(e.value, exact);
+        let p = McParams { exact_threshold: 3, ..p };
+        assert_eq!(evaluate_with_mode(&mut state, &p, &mut SimpleRng::new(1)).mode, SolveMode::Hybrid);
+        let p = McParams { algorithm: Algorithm::Mcts, iterations: 100, ..p };
+        assert_eq!(evaluate_with_mode(&mut state, &p, &mut SimpleRng::new(1)).mode, SolveMode::Mcts);
+    }
+
+    #[test]
+    fn card_classes_match_unabstracted() {
+        // 最後の 1 マス: 同値類でまとめても札ごとの展開と一致 (Joker 2 枚・狭義単調も含む)
+        let mut two_jokers = Rules::default();
+        two_jokers.deck[JOKER as usize] = 2;
+        let strict = Rules { run_order: RunOrder::Increasing, ..two_jokers };
+        let mut rng = SimpleRng::new(11);
+        for rules in [Rules::default(), two_jokers, strict] {
+            for _ in 0..200 {
+                let start = GameState::with_rules(rules, &[0; BOARD_SIZE]).unwrap();
+                let mut board = draw_sequence(&start, &mut rng);
+                board[rng.gen_range(BOARD_SIZE as u8) as usize] = 0;
+                let mut st = GameState::with_rules(rules, &board).unwrap();
+                let p = McParams { sims: 1, rollout_limit: 1, ..Default::default() };
+                let plain = ev_before_draw(&mut st, &p, &mut rng, 0);
+                let abst = ev_before_draw(&mut st, &McParams { lossy_abstraction: true, ..p }, &mut rng, 0);
+                assert!((plain - abst).abs() < 1e-9, "{board:?}: {plain} vs {abst}"); // 足す順による丸めだけ
+                let (_, classes) = card_classes(&st);
+                assert!(classes <= st.deck().iter().filter(|&&c| c > 0).count());
+            }
+        }
+        // それより前は非可逆で一致は保証しない。大きく外れないことだけ見る
+        let mut score_table = [0i32; MAX_BOARD + 1];
+        score_table[..7].copy_from_slice(&[0, 0, 1, 3, 6, 10, 15]);
+        let small = Rules { board_len: 6, score_table, ..Rules::default() };
+        for b in [[5, 0, 0, 20, 0, 25], [0, 8, 12, 0, 0, 30]] {
+            let mut st = GameState::with_rules(small, &b).unwrap();
+            let p = McParams { sims: 1, rollout_limit: 6, ..Default::default() };
+            let plain = ev_before_draw(&mut st, &p, &mut rng, 0);
+            let abst = ev_before_draw(&mut st, &McParams { lossy_abstraction: true, ..p }, &mut rng, 0);
+            assert!((plain - abst).abs() < 0.06 * plain, "{b:?}: {plain} vs {abst}");
+        }
+    }
+
+    #[test]
+    fn upper_bound_prunes_without_changing_values() {
+        // 上界は満盤なら得点そのもの、途中ではどの続きの得点も超えない
+        let mut rng = SimpleRng::new(21);
+        let start = GameState::new([0; BOARD_SIZE]).unwrap();
+        let mut heur = RolloutPolicy::Proportional;
+        for _ in 0..200 {
+            let rec = simulate_from(&start, &mut RandomPolicy(SimpleRng::new(rng.next_u64())), &mut rng);
+            let mut st = start.clone();
+            for (k, m) in rec.moves.iter().enumerate() {
+                st.draw(m.card);
+                st.place(m.pos, m.card);
+                let ub = st.upper_bound();
+                if k + 1 == rec.moves.len() { assert_eq!(ub, rec.score); }
+                assert!(rec.score <= ub);
+                assert!(simulate_from(&st, &mut heur, &mut rng).score <= ub);
+            }
+        }
+        // 全展開 (乱数を使わない) で枝刈りの有無が同じ値になり、厳密解とも一致する
+        let mut st = GameState::new(board_from_str("1234_6789AB_DEFGHI_K").unwrap()).unwrap();
+        let p = McParams { sims: 1, rollout_limit: 3, pruning: false, ..Default::default() };
+        let plain = ev_before_draw(&mut st, &p, &mut rng, 0);
+        let mut prune = PruneStats::default();
+        let cut = before_draw(&mut st, &McParams { pruning: true, ..p }, &mut rng, 0, &mut prune);
+        assert_eq!(plain, cut);
+        assert!(prune.pruned > 0 && prune.expanded > 0, "{prune:?}");
+        let mut tt = TransTable::new(1 << 20);
+        assert!((ev_exact(&mut st, &Objective::Mean, &mut tt) - plain).abs() < 1e-9);
+        assert!(tt.prune.pruned > 0);
+        // P(score >= t) でも同じ
+        let p = McParams { objective: Objective::AtLeast(60), ..p };
+        assert_eq!(ev_before_draw(&mut st, &p, &mut rng, 0), ev_before_draw(&mut st, &McParams { pruning: true, ..p }, &mut rng, 0));
+    }
+
+    #[test]
+    fn root_streams_are_reproducible() {
+        // 同じ種なら (parallel feature の有無にかかわらず) 同じ結果
+        let board = board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap();
+        let run = |seed| {
+            let mut st = GameState::new(board).unwrap();
+            let p = McParams { sims: 8, ..Default::default() };
+            let ev = evaluate(&mut st, &p, &mut SimpleRng::new(seed));
+            let big = rollout(&st, &McParams { sims: 3 * ROLLOUT_CHUNK + 5, ..p }, &mut SimpleRng::new(seed));
+            let rec = recommend(&st, 30, &p, &mut SimpleRng::new(seed)).unwrap();
+            (ev, big, rec.iter().map(|m| (m.pos, m.mean)).collect::<Vec<_>>())
+        };
+        assert_eq!(run(5), run(5));
+        assert_ne!(run(5).0, run(6).0);
+        let r = SimpleRng::new(1);
+        assert_ne!(r.fork(0).next_u64(), r.fork(1).next_u64());
+        let d = score_distribution(&GameState::new(board).unwrap(), &McParams { sims: 2500, ..Default::default() }, &mut SimpleRng::new(2));
+        assert_eq!(d.samples(), 2500);
+    }
+
+    #[test]
+    fn shards_merge_to_recommend() {
+        let st = GameState::new(board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap()).unwrap();
+        let p = McParams { sims: 16, ..Default::default() };
+        let whole = recommend_shard(&st, 20, &p, 77, 0, 1).unwrap();
+        for shards in [2, 3, RECOMMEND_BATCHES + 1] {
+            let parts: Vec<_> = (0..shards).map(|k| recommend_shard(&st, 20, &p, 77, k, shards).unwrap()).collect();
+            let merged = merge_shards(&parts);
+            assert_eq!(merged.len(), whole.len());
+            for m in &merged {
+                let (_, s) = whole.iter().find(|(pos, _)| *pos == m.pos).unwrap();
+                assert_eq!(m.samples, s.n);
+                assert!((m.mean - s.mean).abs() < 1e-9 && (m.stderr - s.stderr()).abs() < 1e-9);
+            }
+        }
+        // Stats の分解・復元
+        let (_, s) = whole[0];
+        let t = Stats::from_parts(s.n, s.mean, s.m2());
+        assert_eq!((t.n, t.mean, t.stderr()), (s.n, s.mean, s.stderr()));
+    }
+
+    #[test]
+    fn proportional_rollout() {
+        let rules = Rules::default();
+        let empty = [0u8; BOARD_SIZE];
+        assert_eq!(proportional_slot(&rules, &empty, 30), 19);
+        assert_eq!(proportional_slot(&rules, &empty, 1), 0);
+        assert!((8..=11).contains(&proportional_slot(&rules, &empty, 15)));
+        let mut b = empty;
+        b[10] = 10;
+        // 左区間 (−1, 10) で 0‒10 の中央付近
+        assert!((4..=6).contains(&proportional_slot(&rules, &b, 5)));
+        // 10 の右側に置く
+        assert!(proportional_slot(&rules, &b, 12) > 10);
+        // 左詰めより大幅に良い
+        let state = GameState::new(empty).unwrap();
+        let left = McParams { sims: 2000, ..Default::default() };
+        let prop = McParams { rollout_policy: RolloutPolicy::Proportional, ..left };
+        let l = rollout(&state, &left, &mut SimpleRng::new(1));
+        let h = rollout(&state, &prop, &mut SimpleRng::new(1));
+        assert!(h > 2.0 * l, "proportional {h} vs left {l}");
+    }
+
+    #[test]
+    fn policies_choose_empty_cells() {
+        let mut b = [0u8; BOARD_SIZE];
+        b[..18].copy_from_slice(&[1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18]);
+        let st = GameState::new(b).unwrap();
+        let p = McParams { sims: 20, ..Default::default() };
+        for name in ["random", "left", "heuristic", "greedy", "search"] {
+            let mut pol = policy_by_name(name, p, 7).unwrap();
+            assert!([18, 19].contains(&pol.choose(&st, 30)), "{name}");
+            // 30 は右端に置かないと連が切れる
+            if name != "random" && name != "left" { assert_eq!(pol.choose(&st, 30), 19, "{name}"); }
+        }
+        // playout_with と Proportional の高速経路は同じ手を打つ
+        let empty = GameState::new([0; BOARD_SIZE]).unwrap();
+        let mut heur = RolloutPolicy::Proportional;
+        assert_eq!(playout_with(&empty, &mut heur, &mut SimpleRng::new(3)),
+                   playout(&empty, RolloutPolicy::Proportional, &mut SimpleRng::new(3)));
+    }
+
+    #[test]
+    fn simulate_full_game() {
+        let mut heur = RolloutPolicy::Proportional;
+        let rec = simulate_game(&mut heur, &mut SimpleRng::new(5));
+        assert_eq!(rec.moves.len(), BOARD_SIZE);
+        let mut seen = [false; BOARD_SIZE];
+        for m in &rec.moves { assert!(!seen[m.pos]); seen[m.pos] = true; assert_eq!(rec.board[m.pos], m.card); }
+        assert_eq!(rec.score, Rules::default().score_board(&rec.board));
+        // 同じ種なら同じドロー列 → 同じ棋譜
+        let start = GameState::new([0; BOARD_SIZE]).unwrap();
+        let draws = draw_sequence(&start, &mut SimpleRng::new(5));
+        assert_eq!(draws, rec.moves.iter().map(|m| m.card).collect::<Vec<_>>());
+        assert_eq!(play_draws(&start, &mut heur, &draws).unwrap().score, rec.score);
+        // 山札に無い札
+        assert!(play_draws(&start, &mut heur, &[JOKER, JOKER]).is_err());
+    }
+
+    #[test]
+    fn tournament_is_reproducible() {
+        let start = GameState::new([0; BOARD_SIZE]).unwrap();
+        let run = || {
+            let mut pols: Vec<Box<dyn Policy>> = ["random", "heuristic"].iter()
+                .map(|n| policy_by_name(n, McParams::default(), 9).unwrap()).collect();
+            tournament(&start, &mut pols, 200, 42)
+        };
+        let (a, b) = (run(), run());
+        assert_eq!(a.dists[0].counts(), b.dists[0].counts());
+        assert_eq!(a.dists[1].counts(), b.dists[1].counts());
+        assert!((a.win_rate(0, 1) + a.win_rate(1, 0) - 1.0).abs() < 1e-9);
+        assert!(a.win_rate(1, 0) > 0.8 && a.stats[1].mean > a.stats[0].mean + 5.0 * a.stats[1].stderr());
+    }
+
+    #[test]
+    fn shared_draw_match() {
+        let start = GameState::new([0; BOARD_SIZE]).unwrap();
+        let mut players: Vec<(GameState, Box<dyn Policy>)> = ["left", "heuristic"].iter()
+            .map(|n| (start.clone(), policy_by_name(n, McParams::default(), 1).unwrap())).collect();
+        let recs = simulate_match(&mut players, &mut SimpleRng::new(4)).unwrap();
+        // 全員が同じ札を同じ順に受け取る
+        let cards = |r: &GameRecord| r.moves.iter().map(|m| m.card).collect::<Vec<_>>();
+        assert_eq!(cards(&recs[0]), cards(&recs[1]));
+        assert_eq!(win_share(&[10, 12, 12], 1), 0.5);
+        assert_eq!(win_share(&[10, 12, 12], 0), 0.0);
+
+        // 相手が終局済みで S 点なら、勝率 = P(自分 > S) + P(自分 = S) / 2
+        let mut me = [0u8; BOARD_SIZE];
+        me[..16].copy_from_slice(&[16,15,14,13,12,11,10,9,8,7,6,5,4,3,2,1]);
+        let deck = *GameState::new(me).unwrap().deck();
+        // 相手の終局盤面を作れるよう、ルール上の札は 2 倍にしておく (山札は両者共通)
+        let mut rules = Rules::default();
+        for c in rules.deck.iter_mut() { *c *= 2; }
+        let me = GameState::with_deck(rules, &me, deck).unwrap();
+        let opp_board = [1,2,3,10,9,8,7,6,5,4,15,14,13,12,11,15,14,13,12,11];
+        let opp = GameState::with_deck(rules, &opp_board, deck).unwrap();
+        let s = opp.score();
+        let mut opps: Vec<(GameState, Box<dyn Policy>)> = vec![(opp, policy_by_name("left", McParams::default(), 1).unwrap())];
+        let p = McParams { sims: 4000, rollout_policy: RolloutPolicy::Proportional, ..Default::default() };
+        let wins = win_probabilities(&me, &mut opps, 20, &p, &mut SimpleRng::new(8)).unwrap();
+        let dists = score_distributions_after_card(&me, 20, &p, &mut SimpleRng::new(9)).unwrap();
+        for m in &wins {
+            let d = &dists.iter().find(|(pos, _)| *pos == m.pos).unwrap().1;
+            let want = d.prob_at_least(s + 1) + 0.5 * (d.prob_at_least(s) - d.prob_at_least(s + 1));
+            assert!((m.mean - want).abs() < 0.05, "pos {} win {} vs {want}", m.pos, m.mean);
+        }
+        assert!(wins.windows(2).all(|w| w[0].mean >= w[1].mean));
+        // 中断されたら 2 試行で止まる
+        let flag = AtomicBool::new(true);
+        let stopped = win_probabilities(&me, &mut opps, 20, &McParams { cancel: Some(&flag), ..p }, &mut SimpleRng::new(8)).unwrap();
+        assert!(stopped.iter().all(|m| m.samples == 2));
+        // 山札が食い違う相手は拒否
+        let mut bad: Vec<(GameState, Box<dyn Policy>)> = vec![(start, policy_by_name("left", McParams::default(), 1).unwrap())];
+        assert_eq!(win_probabilities(&me, &mut bad, 20, &p, &mut SimpleRng::new(8)).unwrap_err(), StreamsError::DeckMismatch { player: 0 });
+    }
+
+    #[test]
+    fn cancel_stops_search_and_progress_is_monotone() {
+        let st = GameState::new(board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap()).unwrap();
+        // 最初から中断: 期限なし・巨大な rollout でもすぐ返る
+        let flag = AtomicBool::new(true);
+        let p = McParams { sims: 1 << 30, cancel: Some(&flag), ..Default::default() };
+        let t0 = now_ms();
+        let ev = evaluate(&mut st.clone(), &p, &mut SimpleRng::new(1));
+        assert!(now_ms() - t0 < 2000.0 && (0.0..=300.0).contains(&ev));
+        // 得点分布は最初のかたまりだけ
+        let p = McParams { sims: 1 << 20, ..p };
+        assert_eq!(score_distribution(&st, &p, &mut SimpleRng::new(1)).samples(), ROLLOUT_CHUNK as u32);
+        // 通知の中から中断する
+        let flag = AtomicBool::new(false);
+        let p = McParams { sims: 64, cancel: Some(&flag), ..Default::default() };
+        let mut seen = Vec::new();
+        let moves = recommend_with_progress(&st, 12, &p, &mut SimpleRng::new(2), &mut |pr| {
+            seen.push(pr.done);
+            if pr.done == 3 { flag.store(true, Ordering::Relaxed); }
+        }).unwrap();
+        assert_eq!(seen, [1, 2, 3]);
+        assert!(moves.iter().all(|m| m.samples == 3));
+        // Hybrid の反復ごとの通知
+        let p = McParams { deadline: Some(Deadline::after(Duration::from_millis(30))), exact_threshold: 0, ..Default::default() };
+        let mut seen = Vec::new();
+        evaluate_with_progress(&mut st.clone(), &p, &mut SimpleRng::new(3), &mut |pr| seen.push((pr.done, pr.total)));
+        assert!(!seen.is_empty() && seen.windows(2).all(|w| w[0].0 < w[1].0 && w[1].0 <= w[1].1));
+        // 完走すれば最後の通知で done = total
+        let p = McParams { deadline: Some(Deadline::after(Duration::from_secs(20))), exact_threshold: 0, sims: 16, ..Default::default() };
+        let mut seen = Vec::new();
+        let mut end = GameState::new(board_from_str("123456789ABCDEFGH___").unwrap()).unwrap();
+        evaluate_with_progress(&mut end, &p, &mut SimpleRng::new(4), &mut |pr| seen.push((pr.done, pr.total)));
+        assert_eq!(seen, (0..=5).map(|d| (d, 5)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn solver_reuses_samples_across_turns() {
+        let st = GameState::new(board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap()).unwrap();
+        let mut s = Solver::new(st.clone(), McParams { sims: 8, ..Default::default() }, 1);
+        let first = s.values_after_card(12, None).unwrap();
+        let again = s.values_after_card(12, None).unwrap();
+        assert!(first.iter().all(|m| m.samples == 1) && again.iter().all(|m| m.samples == 2));
+        // 置いた先は評価済みの局面
+        let pos = again[0].pos;
+        s.place(12, pos).unwrap();
+        assert_eq!(s.value(None).samples, 3);
+        assert_eq!(s.stats().reused, first.len() as u64 + 1);
+        assert_eq!(s.place(12, pos), Err(StreamsError::BadPosition { pos }));
+        assert_eq!(s.undo(), Some(Move { card: 12, pos }));
+        assert_eq!(s.state().board(), st.board());
+        assert_eq!(s.state().deck(), st.deck());
+        // 終盤は厳密解
+        let mut s = Solver::new(GameState::new(board_from_str("123456789ABCDEFGH___").unwrap()).unwrap(), McParams::default(), 1);
+        let e = s.value(None);
+        assert!(e.exact && e.stderr == 0.0);
+    }
+
+    #[test]
+    fn solver_step_accumulates() {
+        let st = GameState::new(board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap()).unwrap();
+        let mut s = Solver::new(st, McParams::default(), 3);
+        assert!(s.step(10).is_empty());
+        s.set_card(Some(20)).unwrap();
+        // 10 マスに 25 本 → 3,3,3,3,3,2,2,2,2,2 → 次の 5 本で全部 3
+        let total = |m: &[MoveStat]| m.iter().map(|m| m.samples).sum::<u32>();
+        assert_eq!(total(&s.step(25)), 25);
+        let moves = s.step(5);
+        assert!(moves.iter().all(|m| m.samples == 3));
+        let moves = s.step(1000);
+        assert!(moves.iter().all(|m| m.stderr.is_finite() && m.gap >= 0.0));
+        assert!(moves.windows(2).all(|w| w[0].mean >= w[1].mean));
+        // 置いたら対象札は外れる
+        s.place(20, moves[0].pos).unwrap();
+        assert_eq!(s.card(), None);
+        assert_eq!(s.set_card(Some(20)).unwrap_err(), StreamsError::OverusedCard { card: 20, pos: None });
+    }
+
+    #[test]
+    fn ev_matrix_rows_match_card_rollouts() {
+        let st = GameState::new(board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap()).unwrap();
+        let p = McParams { sims: 400, rollout_policy: RolloutPolicy::Proportional, ..Default::default() };
+        let m = ev_matrix(&st, &p, &mut SimpleRng::new(4));
+        assert_eq!(m.samples, 400);
+        // 盤上の 1 (1 枚しかない) の行と、埋まったマスの列は空
+        assert!(m.mean(1, 1).is_nan() && m.best(1).is_none() && m.mean(30, 0).is_nan());
+        // 12 は 2 枚とも山札に残っている
+        for card in [2, 12, 20, JOKER] {
+            let best = m.best(card).unwrap();
+            assert_eq!(m.row(card)[0].pos, best);
+            // 同じ札の行を通常の rollout と比べる
+            let mut after = st.after_draw(card).unwrap();
+            after.place(best, card);
+            let direct = rollout(&after, &McParams { sims: 4000, ..p }, &mut SimpleRng::new(5));
+            assert!((m.mean(card, best) - direct).abs() < 4.0 * m.stderr(card, best) + 1.0, "card {card}");
+        }
+    }
+
+    #[test]
+    fn after_card_removes_drawn_card() {
+        // 空き 2 マス・山札 {B, C}: B を引いて置いたら残りは C だけなので値は確定する。
+        // B を山札に残したまま評価すると、次に B を引く分岐が混ざる
+        let board = board_from_str_len("123456789A__DEFGHIJK", BOARD_SIZE).unwrap();
+        let st = GameState::with_deck(Rules::default(), &board, deck_from_str("BC").unwrap()).unwrap();
+        let p = McParams { exact_threshold: 0, ..Default::default() };
+        let vals = evaluate_after_card(&st, 11, &p, &mut SimpleRng::new(1), &mut |_| {}).unwrap();
+        let score = |b: &str| GameState::new(board_from_str(b).unwrap()).unwrap().score() as f64;
+        assert_eq!(vals[10], score("123456789ABCDEFGHIJK"));
+        assert_eq!(vals[11], score("123456789ACBDEFGHIJK"));
+        assert_eq!(vals[0], 0.0);
+        assert!(evaluate_after_card(&st, 12, &McParams::default(), &mut SimpleRng::new(1), &mut |_| {}).is_ok());
+        assert_eq!(evaluate_after_card(&st, 13, &p, &mut SimpleRng::new(1), &mut |_| {}).unwrap_err(),
+                   StreamsError::OverusedCard { card: 13, pos: None });
+    }
+
+    #[test]
+    fn endgame_exact_yields_to_deadline() {
+        // 空き 5 マスの厳密解は秒単位かかる。期限が先に来たら Monte-Carlo の値で返す
+        let mut st = GameState::new(board_from_str("123456789ABCDEF_____").unwrap()).unwrap();
+        let p = McParams { exact_threshold: 6, deadline: Some(Deadline::after(Duration::from_millis(20))), ..Default::default() };
+        let t0 = now_ms();
+        let e = evaluate_with_mode(&mut st, &p, &mut SimpleRng::new(1));
+        assert!(now_ms() - t0 < 1000.0);
+        assert_eq!(e.mode, SolveMode::Hybrid);
+        assert!((0.0..=300.0).contains(&e.value));
+        assert_eq!(st.board(), &board_from_str("123456789ABCDEF_____").unwrap()[..]);
+        // 打ち切った表で解き直しても正しい値になる
+        let mut tt = TransTable::new(ENDGAME_TT_BYTES);
+        let flag = AtomicBool::new(true);
+        assert_eq!(endgame_exact(&mut st, &McParams { exact_threshold: 6, cancel: Some(&flag), ..Default::default() }, &mut tt), None);
+        let mut small = GameState::new(board_from_str("123456789ABCDEFGH___").unwrap()).unwrap();
+        let want = ev_exact(&mut small, &Objective::Mean, &mut TransTable::new(1 << 16));
+        assert_eq!(endgame_exact(&mut small, &McParams::default(), &mut tt), Some(want));
+    }
+
+    #[test]
+    fn ev_matrix_deals_duplicate_cards_uniformly() {
+        // 山札 {C, C, U}・空き 3 マス: C を置いた後の残り 2 枚は C→U と U→C が半々。
+        // 並びから最初の C を抜くと C→U が 1/3 に減る
+        let board = board_from_str("123456789A__DEFGH_JK").unwrap();
+        let st = GameState::with_deck(Rules::default(), &board, deck_from_str("CCU").unwrap()).unwrap();
+        let p = McParams { sims: 4000, ..Default::default() };
+        let m = ev_matrix(&st, &p, &mut SimpleRng::new(6));
+        let mut after = st.after_draw(12).unwrap();
+        after.place(10, 12);
+        let left = |draws: &[u8]| play_draws(&after, &mut RolloutPolicy::LeftToRight, draws).unwrap().score as f64;
+        let (a, b) = (left(&[12, 30]), left(&[30, 12]));
+        assert!(a != b);
+        let want = (a + b) / 2.0;
+        assert!((m.mean(12, 10) - want).abs() < 4.0 * m.stderr(12, 10), "{} vs {want}", m.mean(12, 10));
+    }
+
+    #[test]
+    fn anytime_respects_budget() {
+        let board = board_from_str("1_3_5_7_9_B_D_F_H_J_").unwrap();
+        let mut state = GameState::new(board).unwrap();
+        let params = McParams { deadline: Some(Deadline::after(Duration::from_millis(50))), ..Default::default() };
+        let mut rng = SimpleRng::new(7);
+        let t0 = now_ms();
+        let ev = ev_anytime(&mut state, &params, &mut rng);
+        assert!(now_ms() - t0 < 2000.0);
+        assert!((0.0..=300.0).contains(&ev));
+    }
 }
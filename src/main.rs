@@ -1,16 +1,202 @@
-use streams_solver::{ GameState, McParams, ev_before_draw, SimpleRng, board_from_str };
+use streams_solver::{ Algorithm, RolloutPolicy, GameState, McParams, Objective, Deadline, evaluate_with_mode, ev_exact, policy_by_name, tournament, Policy, StreamsError, score_distribution, SimpleRng, TransTable, Rules, board_from_str, deck_from_str, ev_matrix, card_to_char };
+use std::time::Duration;
 /*────────────── CLI テスト ─────────────*/
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
 
     let arg: Vec<String> = std::env::args().collect();
-    if arg.len() != 2 {
-        eprintln!("usage: {} <board20>", arg[0]);
+    if arg.get(1).is_some_and(|a| a == "bench") { bench(&arg); return; }
+    let usage = || -> ! {
+        eprintln!("usage: {} bench [--games <n>] [--seed <s>] [--sims <n>] [--board <board20>] <policy>...", arg[0]);
+        eprintln!("       {} <board20> [--deck <cards>] [--exact | --mcts [--iters <n>]] [--seed <s>] [--heuristic] [--lossy-abstract] [--exact-below <n>] [--policy <name> --card <c>] [--time <ms>] [--dist <sims> | --matrix <sims>] [--target <score> | --cvar <alpha>]", arg[0]);
         std::process::exit(1);
+    };
+    let mut board_str = None;
+    let mut exact = false;
+    let mut deck_str = None;
+    let mut algorithm = Algorithm::Hybrid;
+    let mut iterations = None;
+    let mut rollout_policy = RolloutPolicy::LeftToRight;
+    let mut seed = None;
+    let mut exact_below = None;
+    let mut abstraction = false;
+    let mut policy = None;
+    let mut card = None;
+    let mut time_ms = None;
+    let mut dist_sims = None;
+    let mut matrix_sims = None;
+    let mut target = None;
+    let mut cvar = None;
+    let mut it = arg[1..].iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "--exact" => exact = true,
+            "--mcts"  => algorithm = Algorithm::Mcts,
+            "--iters" => iterations = Some(it.next().and_then(|v| v.parse::<usize>().ok()).unwrap_or_else(|| usage())),
+            "--heuristic" => rollout_policy = RolloutPolicy::Proportional,
+            "--seed"  => seed = Some(it.next().and_then(|v| v.parse::<u64>().ok()).unwrap_or_else(|| usage())),
+            "--lossy-abstract" => abstraction = true,
+            "--exact-below" => exact_below = Some(it.next().and_then(|v| v.parse::<usize>().ok()).unwrap_or_else(|| usage())),
+            "--policy" => policy = Some(it.next().unwrap_or_else(|| usage())),
+            "--card"  => card = Some(it.next().and_then(|v| v.parse::<u8>().ok()).filter(|c| (1..=31).contains(c)).unwrap_or_else(|| usage())),
+            "--deck"  => deck_str = Some(it.next().unwrap_or_else(|| usage())),
+            "--time"  => time_ms = Some(it.next().and_then(|v| v.parse::<u64>().ok()).unwrap_or_else(|| usage())),
+            "--matrix" => matrix_sims = Some(it.next().and_then(|v| v.parse::<usize>().ok()).unwrap_or_else(|| usage())),
+            "--dist"  => dist_sims = Some(it.next().and_then(|v| v.parse::<usize>().ok()).unwrap_or_else(|| usage())),
+            "--target" => target = Some(it.next().and_then(|v| v.parse::<i32>().ok()).unwrap_or_else(|| usage())),
+            "--cvar"  => cvar = Some(it.next().and_then(|v| v.parse::<f64>().ok()).unwrap_or_else(|| usage())),
+            _ if board_str.is_none() => board_str = Some(a),
+            _ => usage(),
+        }
     }
-    let board = board_from_str(&arg[1]).expect("board");
-    let mut st = GameState::new(board);
-    let p = McParams::default();
-    let mut rng = SimpleRng::default();
-    println!("EV = {:.3}", ev_before_draw(&mut st, &p, &mut rng, 0));
-}
\ No newline at end of file
+    let Some(board_str) = board_str else { usage() };
+    let parsed = board_from_str(board_str).and_then(|b| match deck_str {
+        Some(d) => GameState::with_deck(Rules::default(), &b, deck_from_str(d)?),
+        None    => GameState::new(b),
+    });
+    let mut st = match parsed {
+        Ok(st) => st,
+        Err(e) => { eprintln!("error: {e}"); std::process::exit(1); },
+    };
+    // 種を指定すれば (parallel feature でも) 再現する
+    let mut rng = seed.map_or_else(SimpleRng::default, SimpleRng::new);
+    if let Some(sims) = dist_sims {
+        let p = McParams { sims, rollout_policy, ..Default::default() };
+        let d = score_distribution(&st, &p, &mut rng);
+        println!("mean = {:.3}  p10 = {}  p50 = {}  p90 = {}", d.mean(), d.quantile(0.1), d.quantile(0.5), d.quantile(0.9));
+        for (i, &c) in d.counts().iter().enumerate().filter(|&(_, &c)| c > 0) {
+            println!("{:>4} {c}", d.min_score() + i as i32);
+        }
+        return;
+    }
+    let objective = match (target, cvar) {
+        (Some(t), _)     => Objective::AtLeast(t),
+        (None, Some(a))  => Objective::cvar(a, &st, &McParams { sims: 1000, ..Default::default() }, &mut rng),
+        (None, None)     => Objective::Mean,
+    };
+    let deadline = time_ms.map(|ms| Deadline::after(Duration::from_millis(ms)));
+    let mut p = McParams { deadline, objective, algorithm, rollout_policy, ..Default::default() };
+    if let Some(n) = iterations { p.iterations = n; }
+    if let Some(n) = exact_below { p.exact_threshold = n; }
+    p.lossy_abstraction = abstraction;
+    if let Some(sims) = matrix_sims {
+        // 札 × マスの表 (行 = 札、最後の列 = 最善マス)
+        let m = ev_matrix(&st, &McParams { sims, ..p }, &mut rng);
+        print!("card");
+        for pos in 0..m.board_len { print!(" {pos:>6}"); }
+        println!("  best");
+        for card in 1..=31u8 {
+            let Some(best) = m.best(card) else { continue };
+            print!("{:>4}", card_to_char(card).unwrap_or('?'));
+            for pos in 0..m.board_len {
+                let v = m.mean(card, pos);
+                if v.is_nan() { print!(" {:>6}", "-"); } else { print!(" {v:>6.1}"); }
+            }
+            println!("  {best:>4}");
+        }
+        println!("{} samples", m.samples);
+        return;
+    }
+    if let Some(card) = card {
+        let name = policy.map_or("search", |s| s.as_str());
+        let mut pol = policy_by_name(name, p, seed.unwrap_or_else(|| rng.next_u64())).unwrap_or_else(|| usage());
+        let err = if !st.board().contains(&0) { Some(StreamsError::FullBoard) }
+            else if st.deck()[card as usize] == 0 { Some(StreamsError::OverusedCard { card, pos: None }) }
+            else { None };
+        if let Some(e) = err { eprintln!("error: {e}"); std::process::exit(1); }
+        println!("{name}: card {card} -> pos {}", pol.choose(&st, card));
+        return;
+    }
+    if exact {
+        let mut tt = TransTable::default();
+        println!("{} (exact) = {:.3}", objective_label(&objective), ev_exact(&mut st, &objective, &mut tt));
+        println!("expanded = {}  pruned = {}  tt hits = {}", tt.prune.expanded, tt.prune.pruned, tt.hits);
+        return;
+    }
+    let e = evaluate_with_mode(&mut st, &p, &mut rng);
+    println!("{} ({}) = {:.3}", objective_label(&objective), format!("{:?}", e.mode).to_lowercase(), e.value);
+    if e.prune.expanded > 0 { println!("expanded = {}  pruned = {}", e.prune.expanded, e.prune.pruned); }
+}
+
+/// 出力の見出し: 何の値か (期待値・確率・CVaR の下界)
+fn objective_label(o: &Objective) -> String {
+    match *o {
+        Objective::Mean              => "EV".into(),
+        Objective::AtLeast(t)        => format!("P(score>={t})"),
+        Objective::Cvar { alpha, .. } => format!("CVaR({alpha}) lower bound"),
+        Objective::Utility(_)        => "utility".into(),
+    }
+}
+
+/// bench サブコマンド: 方策ごとに同じドロー列で N 局打って比較する
+#[cfg(not(target_arch = "wasm32"))]
+fn bench(arg: &[String]) {
+    let usage = || -> ! {
+        eprintln!("usage: {} bench [--games <n>] [--seed <s>] [--sims <n>] [--board <board20>] <policy>...", arg[0]);
+        eprintln!("policies: random left heuristic greedy search");
+        std::process::exit(1);
+    };
+    let mut games = 1000;
+    let mut seed = 1;
+    let mut p = McParams::default();
+    let mut board = [0u8; 20];
+    let mut names = Vec::new();
+    let mut it = arg[2..].iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "--games" => games = it.next().and_then(|v| v.parse::<usize>().ok()).unwrap_or_else(|| usage()),
+            "--seed"  => seed = it.next().and_then(|v| v.parse::<u64>().ok()).unwrap_or_else(|| usage()),
+            "--sims"  => p.sims = it.next().and_then(|v| v.parse::<usize>().ok()).unwrap_or_else(|| usage()),
+            "--board" => board = match board_from_str(it.next().unwrap_or_else(|| usage())) {
+                Ok(b) => b,
+                Err(e) => { eprintln!("error: {e}"); std::process::exit(1); },
+            },
+            _ => names.push(a.as_str()),
+        }
+    }
+    if names.is_empty() { usage(); }
+    let st = match GameState::new(board) {
+        Ok(st) => st,
+        Err(e) => { eprintln!("error: {e}"); std::process::exit(1); },
+    };
+    // 方策側の乱数も seed から決める
+    let mut policies: Vec<Box<dyn Policy>> = names.iter().enumerate()
+        .map(|(i, n)| policy_by_name(n, p, seed.wrapping_add(i as u64 + 1)).unwrap_or_else(|| usage()))
+        .collect();
+    let t = tournament(&st, &mut policies, games, seed);
+
+    println!("{games} games, seed {seed}");
+    println!("{:<10} {:>8} {:>7} {:>5} {:>5} {:>5}", "policy", "mean", "stderr", "p10", "p50", "p90");
+    for (i, n) in names.iter().enumerate() {
+        let d = &t.dists[i];
+        println!("{n:<10} {:>8.3} {:>7.3} {:>5} {:>5} {:>5}",
+            t.stats[i].mean, t.stats[i].stderr(), d.quantile(0.1), d.quantile(0.5), d.quantile(0.9));
+    }
+    println!("\nhead-to-head win rate (row vs column, ties = 1/2)");
+    print!("{:<10}", "");
+    for n in &names { print!(" {n:>10}"); }
+    println!();
+    for (i, n) in names.iter().enumerate() {
+        print!("{n:<10}");
+        for j in 0..names.len() {
+            if i == j { print!(" {:>10}", "-"); } else { print!(" {:>10.3}", t.win_rate(i, j)); }
+        }
+        println!();
+    }
+    // 5 点刻みのヒストグラム
+    for (i, n) in names.iter().enumerate() {
+        println!("\n{n}");
+        let counts = t.dists[i].counts();
+        // 最初の区切りは min_score 以下の 5 の倍数
+        let min = t.dists[i].min_score();
+        let lo = min.div_euclid(5) * 5;
+        let skip = (min - lo) as usize;
+        let mut buckets = vec![0u32; (counts.len() + skip).div_ceil(5)];
+        for (s, &c) in counts.iter().enumerate() { buckets[(s + skip) / 5] += c; }
+        let top = buckets.iter().copied().max().unwrap_or(1).max(1);
+        for (b, &c) in buckets.iter().enumerate().filter(|&(_, &c)| c > 0) {
+            let from = lo + b as i32 * 5;
+            println!("{:>4}-{:<4} {:>6} {}", from, from + 4, c, "#".repeat((c as usize * 40).div_ceil(top as usize)));
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! 札 × マスの配置評価表
+//! ───────────────────────────────────────────────────────────
+//! * 「どの札を引いたらどこに置くか」の早見表を 1 回の呼び出しで作る
+//! * 1 標本ごとに山札全体を 1 回シャッフルし、全札・全マスで同じ並びを使う (common random numbers)。
+//!   札 c の行は並びの中の c のうち一様に選んだ 1 枚を抜いた残りを引く — これは「山札 − c」の
+//!   一様な並びになる (最初の c を抜くと、2 枚ある札の残り 1 枚が後ろに偏る)
+//! * 残りは `p.rollout_policy` で打つ。推定は rollout だけなので `recommend` より粗い
+
+use crate::{GameState, JOKER, McParams, MoveStat, SimpleRng, Stats, par_map, play_draws, sample_card, sort_moves};
+
+/// `ev_matrix` の結果。行 = 札 1‒31 (Joker = 31)、列 = マス
+#[derive(Clone, Debug)]
+pub struct EvMatrix {
+    pub board_len: usize,
+    /// 行優先 (card - 1) * board_len + pos。山札に無い札と埋まったマスは n = 0
+    pub stats: Vec<Stats>,
+    /// 使ったドロー列の本数
+    pub samples: usize,
+}
+impl EvMatrix {
+    #[inline] fn cell(&self, card: u8, pos: usize) -> &Stats { &self.stats[(card as usize - 1) * self.board_len + pos] }
+    /// `card` を `pos` に置いたときの効用の推定値 (評価していなければ NaN)
+    pub fn mean(&self, card: u8, pos: usize) -> f64 {
+        let s = self.cell(card, pos);
+        if s.n == 0 { f64::NAN } else { s.mean }
+    }
+    pub fn stderr(&self, card: u8, pos: usize) -> f64 { self.cell(card, pos).stderr() }
+    /// `card` の最善マス (山札に無い札・空きが無ければ None)
+    pub fn best(&self, card: u8) -> Option<usize> {
+        (0..self.board_len).filter(|&pos| self.cell(card, pos).n > 0)
+            .max_by(|&a, &b| self.cell(card, a).mean.total_cmp(&self.cell(card, b).mean))
+    }
+    /// `card` の行を `recommend` と同じ形で
+    pub fn row(&self, card: u8) -> Vec<MoveStat> {
+        let mut moves: Vec<MoveStat> = (0..self.board_len).filter(|&pos| self.cell(card, pos).n > 0).map(|pos| {
+            let s = self.cell(card, pos);
+            MoveStat { pos, mean: s.mean, stderr: s.stderr(), samples: s.n, gap: 0.0 }
+        }).collect();
+        sort_moves(&mut moves);
+        moves
+    }
+}
+
+/// 山札に残る全札 × 全空きマスについて「引いてそこに置いた」後の効用を `p.sims` 本のドロー列で推定する。
+/// 期限付きなら 2 本目以降で打ち切る
+pub fn ev_matrix(st: &GameState, p: &McParams, rng: &mut SimpleRng) -> EvMatrix {
+    let len = st.rules().board_len;
+    let mut m = EvMatrix { board_len: len, stats: vec![Stats::default(); JOKER as usize * len], samples: 0 };
+    let empties: Vec<usize> = st.empty_positions().collect();
+    let cards: Vec<u8> = (1..=JOKER).filter(|&c| st.deck()[c as usize] > 0).collect();
+    if empties.is_empty() { return m; }
+    for n in 0..p.sims.max(1) {
+        if n >= 2 && p.stopped() { break; }
+        let order = shuffled_deck(st, rng);
+        // 札ごとに抜く 1 枚 (並びの中の何番目の c か) を一様に選ぶ
+        let picks: Vec<usize> = cards.iter().map(|&card| {
+            let copies = order.iter().filter(|&&c| c == card).count();
+            let k = rng.gen_range(copies as u8) as usize;
+            order.iter().enumerate().filter(|&(_, &c)| c == card).nth(k).map_or(0, |(i, _)| i)
+        }).collect();
+        let rows = par_map(cards.len(), |i| {
+            let card = cards[i];
+            let rest: Vec<u8> = order.iter().enumerate().filter(|&(j, _)| j != picks[i]).map(|(_, &c)| c)
+                .take(empties.len() - 1).collect();
+            let mut st = st.after_draw(card).expect("card is in the deck");
+            let mut policy = p.rollout_policy;
+            empties.iter().map(|&pos| {
+                st.place(pos, card);
+                let score = play_draws(&st, &mut policy, &rest).expect("draws come from the deck").score;
+                st.remove(pos);
+                p.objective.utility(score)
+            }).collect::<Vec<f64>>()
+        });
+        for (&card, row) in cards.iter().zip(rows) {
+            for (&pos, v) in empties.iter().zip(row) {
+                m.stats[(card as usize - 1) * len + pos].push(v);
+            }
+        }
+        m.samples += 1;
+    }
+    m
+}
+
+/// 山札全体の一様な並び
+fn shuffled_deck(st: &GameState, rng: &mut SimpleRng) -> Vec<u8> {
+    let mut deck = *st.deck();
+    let mut len = st.deck_len() as u8;
+    (0..len).map(|_| {
+        let card = sample_card(&deck, len, rng);
+        deck[card as usize] -= 1;
+        len -= 1;
+        card
+    }).collect()
+}
@@ -0,0 +1,151 @@
+//! UCT (Monte-Carlo Tree Search)
+//! ───────────────────────────────────────────────────────────
+//! * chance node = 山札からのドロー (progressive widening で札を徐々に追加)
+//! * decision node = 引いた札を置くマス (UCB1)
+//! * ノードは Vec アリーナ、盤面は GameState の place/remove で巻き戻す
+
+use crate::{GameState, McParams, Progress, SimpleRng, playout, sample_card};
+
+const ROOT: u32 = 0;
+/// UCB1 の探索係数 (報酬は観測済みの最小‒最大で正規化)
+const UCT_C: f64 = 1.4;
+/// chance node の子は ceil(PW_K * visits^PW_ALPHA) 枚まで
+const PW_K: f64 = 2.0;
+const PW_ALPHA: f64 = 0.5;
+
+#[derive(Default)]
+struct Node {
+    visits: u32,
+    total: f64,
+    /// (札 or マス, 子ノード)
+    children: Vec<(u8, u32)>,
+}
+impl Node {
+    #[inline] fn mean(&self) -> f64 { if self.visits == 0 { 0.0 } else { self.total / self.visits as f64 } }
+}
+
+struct Tree { nodes: Vec<Node>, lo: f64, hi: f64 }
+
+impl Tree {
+    fn new() -> Self { Self { nodes: vec![Node::default()], lo: f64::INFINITY, hi: f64::NEG_INFINITY } }
+
+    fn add_child(&mut self, parent: u32, key: u8) -> u32 {
+        let ix = self.nodes.len() as u32;
+        self.nodes.push(Node::default());
+        self.nodes[parent as usize].children.push((key, ix));
+        ix
+    }
+
+    /// chance node でドローする札を決め、対応する decision node を返す
+    fn pick_card(&mut self, chance: u32, st: &GameState, rng: &mut SimpleRng) -> (u8, u32) {
+        let card = sample_card(&st.deck_count, st.deck_len, rng);
+        let node = &self.nodes[chance as usize];
+        if let Some(&(_, ix)) = node.children.iter().find(|&&(c, _)| c == card) { return (card, ix); }
+        let allowed = (PW_K * (node.visits.max(1) as f64).powf(PW_ALPHA)).ceil() as usize;
+        if node.children.len() < allowed { return (card, self.add_child(chance, card)); }
+        // 広げない: 既存の子から残り枚数に比例して選ぶ
+        let total: u64 = node.children.iter().map(|&(c, _)| st.deck_count[c as usize] as u64).sum();
+        let mut r = rng.next_u64() % total;
+        for &(c, ix) in &node.children {
+            let w = st.deck_count[c as usize] as u64;
+            if r < w { return (c, ix); }
+            r -= w;
+        }
+        unreachable!("children weights cover r")
+    }
+
+    /// 訪問済みの子から UCB1 最大のマス
+    fn select(&self, dec: u32) -> (usize, u32) {
+        let node = &self.nodes[dec as usize];
+        let ln_n = (node.visits.max(1) as f64).ln();
+        let span = (self.hi - self.lo).max(1e-9);
+        let ucb = |ix: u32| {
+            let ch = &self.nodes[ix as usize];
+            (ch.mean() - self.lo) / span + UCT_C * (ln_n / ch.visits.max(1) as f64).sqrt()
+        };
+        let &(pos, ix) = node.children.iter()
+            .max_by(|a, b| ucb(a.1).total_cmp(&ucb(b.1)))
+            .expect("selected node has children");
+        (pos as usize, ix)
+    }
+
+    /// 1 反復: 選択 → 展開 → rollout → 逆伝播。盤面は元に戻して返す
+    fn iterate(&mut self, st: &mut GameState, p: &McParams, rng: &mut SimpleRng) {
+        let mut path = vec![ROOT];
+        let mut moves: Vec<(u8, usize)> = Vec::new();
+        let mut chance = ROOT;
+        let value = loop {
+            if st.deck_len == 0 || st.empty_positions().next().is_none() {
+                break p.objective.utility(st.score());
+            }
+            let (card, dec) = self.pick_card(chance, st, rng);
+            st.draw(card);
+            path.push(dec);
+            let tried = &self.nodes[dec as usize].children;
+            let untried = st.empty_positions().find(|&pos| tried.iter().all(|&(q, _)| q as usize != pos));
+            match untried {
+                Some(pos) => {
+                    st.place(pos, card);
+                    moves.push((card, pos));
+                    path.push(self.add_child(dec, pos as u8));
+                    break p.objective.utility(playout(st, p.rollout_policy, rng));
+                },
+                None => {
+                    let (pos, next) = self.select(dec);
+                    st.place(pos, card);
+                    moves.push((card, pos));
+                    path.push(next);
+                    chance = next;
+                },
+            }
+        };
+        self.lo = self.lo.min(value);
+        self.hi = self.hi.max(value);
+        for &ix in &path {
+            let n = &mut self.nodes[ix as usize];
+            n.visits += 1;
+            n.total += value;
+        }
+        for &(card, pos) in moves.iter().rev() {
+            st.remove(pos);
+            st.undraw(card);
+        }
+    }
+
+    /// 根の値: 展開済みの札ごとに最多訪問のマスの平均を取り、残り枚数で加重平均する
+    /// (全反復の平均は探索中の悪手を含むので低めに出る)
+    fn root_value(&self, st: &GameState) -> f64 {
+        let (mut sum, mut weight) = (0.0f64, 0.0f64);
+        for &(card, dec) in &self.nodes[ROOT as usize].children {
+            let best = self.nodes[dec as usize].children.iter()
+                .map(|&(_, ix)| &self.nodes[ix as usize])
+                .max_by_key(|n| n.visits);
+            let Some(best) = best else { continue };
+            let w = st.deck_count[card as usize] as f64;
+            sum += w * best.mean();
+            weight += w;
+        }
+        if weight > 0.0 { sum / weight } else { self.nodes[ROOT as usize].mean() }
+    }
+}
+
+/// UCT でドロー前の局面を評価する。`p.iterations` 回か期限切れ・中断で止まる
+pub fn ev_mcts(st: &mut GameState, p: &McParams, rng: &mut SimpleRng) -> f64 {
+    search(st, p, rng, &mut |_| {})
+}
+
+/// `ev_mcts` 本体。1024 反復ごとに根の値を `on_progress` に渡す
+pub(crate) fn search(st: &mut GameState, p: &McParams, rng: &mut SimpleRng, on_progress: &mut dyn FnMut(&Progress)) -> f64 {
+    let mut tree = Tree::new();
+    let total = p.iterations.max(1);
+    let mut done = total;
+    for i in 0..total {
+        // 期限と中断は 64 反復ごとに確認
+        if i > 0 && i % 64 == 0 && p.stopped() { done = i; break; }
+        if i > 0 && i % 1024 == 0 { on_progress(&Progress { done: i, total, estimate: tree.root_value(st) }); }
+        tree.iterate(st, p, rng);
+    }
+    let v = tree.root_value(st);
+    on_progress(&Progress { done, total, estimate: v });
+    v
+}
@@ -0,0 +1,101 @@
+//! 配置方策 (Policy)
+//! ───────────────────────────────────────────────────────────
+//! * `Policy::choose(state, card)` = 「`card` を引いた。どこに置くか」
+//! * state はドロー前 (`card` はまだ山札に入っている) — `recommend` と同じ約束
+//! * 乱数や探索パラメータは各方策が自前で持つ (`&mut self`)
+//! * 探索 (`evaluate` / `recommend` / `score_distribution` など) の rollout は `McParams::rollout_policy`
+//!   (`RolloutPolicy` の 2 種) だけで打つ。任意の `Policy` を使えるのは `playout_with`・`play_draws`・
+//!   `play_match` など 1 局を打つ関数と、相手の方策 (`win_probabilities`) まで
+
+use core::time::Duration;
+use crate::{Deadline, GameState, McParams, RolloutPolicy, SimpleRng, proportional_slot, recommend, rollout, sample_card};
+
+/// 引いた札の置き場所を決める戦略 (探索内部の rollout には差し込めない — モジュール先頭を参照)
+pub trait Policy {
+    /// `card` を置く空きマスの index。`st` には空きマスがあり、山札に `card` が残っていること
+    fn choose(&mut self, st: &GameState, card: u8) -> usize;
+}
+
+impl<P: Policy + ?Sized> Policy for &mut P {
+    fn choose(&mut self, st: &GameState, card: u8) -> usize { (**self).choose(st, card) }
+}
+impl<P: Policy + ?Sized> Policy for Box<P> {
+    fn choose(&mut self, st: &GameState, card: u8) -> usize { (**self).choose(st, card) }
+}
+
+/// rollout 用の軽量方策もそのまま使える (LeftToRight = 左詰め, Proportional = ヒューリスティック)
+impl Policy for RolloutPolicy {
+    fn choose(&mut self, st: &GameState, card: u8) -> usize {
+        match self {
+            RolloutPolicy::LeftToRight  => st.empty_positions().next().unwrap_or(0),
+            RolloutPolicy::Proportional => proportional_slot(&st.rules, st.board(), card),
+        }
+    }
+}
+
+/// 空きマスを一様ランダムに選ぶ
+#[derive(Clone, Copy, Default)]
+pub struct RandomPolicy(pub SimpleRng);
+impl Policy for RandomPolicy {
+    fn choose(&mut self, st: &GameState, _card: u8) -> usize {
+        let n = st.empty_positions().count();
+        st.empty_positions().nth(self.0.gen_range(n as u8) as usize).unwrap_or(0)
+    }
+}
+
+/// 1 手読み: 各空きマスに置いた局面を rollout (`params.sims` 回) で評価して最大を取る
+#[derive(Clone, Copy)]
+pub struct GreedyPolicy<'a> { pub params: McParams<'a>, pub rng: SimpleRng }
+impl Policy for GreedyPolicy<'_> {
+    fn choose(&mut self, st: &GameState, card: u8) -> usize {
+        let Ok(mut st) = st.after_draw(card) else { return 0 };
+        let empties: Vec<usize> = st.empty_positions().collect();
+        let mut best = (f64::NEG_INFINITY, 0);
+        for pos in empties {
+            st.place(pos, card);
+            let v = rollout(&st, &self.params, &mut self.rng);
+            st.remove(pos);
+            if v > best.0 { best = (v, pos); }
+        }
+        best.1
+    }
+}
+
+/// `recommend` (= `params.algorithm` の探索) の最善手。`budget` は 1 手ごとの持ち時間で、
+/// `params.deadline` より優先される
+#[derive(Clone, Copy)]
+pub struct SearchPolicy<'a> { pub params: McParams<'a>, pub budget: Option<Duration>, pub rng: SimpleRng }
+impl Policy for SearchPolicy<'_> {
+    fn choose(&mut self, st: &GameState, card: u8) -> usize {
+        let mut p = self.params;
+        if let Some(b) = self.budget { p.deadline = Some(Deadline::after(b)); }
+        recommend(st, card, &p, &mut self.rng).ok()
+            .and_then(|m| m.first().map(|m| m.pos))
+            .unwrap_or(0)
+    }
+}
+
+/// 名前から方策を作る (CLI 用): random / left / heuristic / greedy / search
+pub fn policy_by_name<'a>(name: &str, params: McParams<'a>, seed: u64) -> Option<Box<dyn Policy + 'a>> {
+    let rng = SimpleRng::new(seed);
+    Some(match name {
+        "random"    => Box::new(RandomPolicy(rng)),
+        "left"      => Box::new(RolloutPolicy::LeftToRight),
+        "heuristic" => Box::new(RolloutPolicy::Proportional),
+        "greedy"    => Box::new(GreedyPolicy { params, rng }),
+        "search"    => Box::new(SearchPolicy { params, budget: None, rng }),
+        _ => return None,
+    })
+}
+
+/// 任意の方策で山札が尽きるか盤が埋まるまで打った最終得点 (1 試行)
+pub fn playout_with<P: Policy + ?Sized>(st: &GameState, policy: &mut P, rng: &mut SimpleRng) -> i32 {
+    let mut st = st.clone();
+    while st.deck_len > 0 && st.empty_positions().next().is_some() {
+        let card = sample_card(&st.deck_count, st.deck_len, rng);
+        let pos = policy.choose(&st, card);
+        st.draw(card);
+        st.place(pos, card);
+    }
+    st.score()
+}
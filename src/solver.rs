@@ -0,0 +1,182 @@
+//! 1 局を通して持ち続けるソルバ (UI 用)
+//! ───────────────────────────────────────────────────────────
+//! * 局面と手順を持ち、`place` / `undo` で進め戻しする
+//! * ドロー前の局面ごとの推定値を `GameState::key` で貯め、問い合わせるたびに 1 バッチ足す
+//! * `values_after_card` で評価した「置いた後」の局面は次の手番の局面そのものなので、
+//!   `place` の後の `value` はその標本から続きを積む
+//! * 終盤 (空きが `exact_threshold` 未満) は厳密解 (持ち時間内に解けなければ Monte-Carlo)。
+//!   置換表も手番をまたいで持ち越す
+//! * `set_card` + `step(n)` は少しずつ rollout を足す anytime 版 (UI の 1 フレームごとに呼ぶ用)
+
+use std::collections::HashMap;
+use core::time::Duration;
+use crate::{Deadline, ENDGAME_TT_BYTES, GameState, McParams, Move, MoveStat, SimpleRng, Stats, StreamsError, TransTable,
+            endgame_exact, evaluate, playout, sort_moves};
+
+/// 1 局面の推定値。厳密解なら `exact` で stderr = 0
+#[derive(Clone, Copy, Debug)]
+pub struct Estimate { pub mean: f64, pub stderr: f64, pub samples: u32, pub exact: bool }
+
+/// キャッシュの規模
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SolverStats {
+    /// 推定値を持っている局面数
+    pub positions: usize,
+    /// 全局面の標本数の合計
+    pub samples: u64,
+    /// 既に標本のある局面に足した回数 (= 前の問い合わせを再利用した回数)
+    pub reused: u64,
+    /// 厳密解の置換表ヒット
+    pub tt_hits: u64,
+}
+
+/// 局面・手順・推定値のキャッシュを持つソルバ
+pub struct Solver {
+    state: GameState,
+    history: Vec<Move>,
+    params: McParams<'static>,
+    rng: SimpleRng,
+    cache: HashMap<u64, Stats>,
+    tt: TransTable,
+    reused: u64,
+    /// `step` の対象札
+    card: Option<u8>,
+    /// `step` の rollout 1 本ずつの統計 (置いた後の局面ごと。`cache` とは別の推定量)
+    rollouts: HashMap<u64, Stats>,
+    /// `step` が次に rollout を足すマスの番号 (空きマス内の順位)
+    cursor: usize,
+}
+
+impl Solver {
+    /// `params.deadline` は無視する (持ち時間は問い合わせごとに渡す)
+    pub fn new(state: GameState, params: McParams<'static>, seed: u64) -> Self {
+        Self {
+            state, history: Vec::new(), params: McParams { deadline: None, ..params }, rng: SimpleRng::new(seed),
+            cache: HashMap::new(), tt: TransTable::new(ENDGAME_TT_BYTES), reused: 0,
+            card: None, rollouts: HashMap::new(), cursor: 0,
+        }
+    }
+
+    #[inline] pub fn state(&self) -> &GameState { &self.state }
+    #[inline] pub fn history(&self) -> &[Move] { &self.history }
+    #[inline] pub fn params(&self) -> &McParams<'static> { &self.params }
+
+    /// 探索パラメータを変える。目的関数が変わりうるのでキャッシュは捨てる
+    pub fn set_params(&mut self, params: McParams<'static>) {
+        self.params = McParams { deadline: None, ..params };
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.tt.clear();
+        self.reused = 0;
+        self.rollouts.clear();
+    }
+
+    pub fn stats(&self) -> SolverStats {
+        SolverStats {
+            positions: self.cache.len(),
+            samples: self.cache.values().chain(self.rollouts.values()).map(|s| s.n as u64).sum(),
+            reused: self.reused,
+            tt_hits: self.tt.hits,
+        }
+    }
+
+    /// 引いた `card` を `pos` に置く
+    pub fn place(&mut self, card: u8, pos: usize) -> Result<(), StreamsError> {
+        if self.state.board().get(pos) != Some(&0) { return Err(StreamsError::BadPosition { pos }); }
+        if !self.state.draw(card) { return Err(StreamsError::OverusedCard { card, pos: Some(pos) }); }
+        self.state.place(pos, card);
+        self.history.push(Move { card, pos });
+        self.card = None;
+        Ok(())
+    }
+
+    /// 直前の `place` を取り消す
+    pub fn undo(&mut self) -> Option<Move> {
+        let m = self.history.pop()?;
+        self.state.remove(m.pos);
+        self.state.undraw(m.card);
+        self.card = None;
+        Some(m)
+    }
+
+    /// `step` で評価する札 (引いたがまだ置いていない札)。None で解除
+    pub fn set_card(&mut self, card: Option<u8>) -> Result<(), StreamsError> {
+        if let Some(c) = card { self.state.after_draw(c)?; }
+        self.card = card;
+        self.cursor = 0;
+        Ok(())
+    }
+    #[inline] pub fn card(&self) -> Option<u8> { self.card }
+
+    /// `set_card` の札を各空きマスに置いた局面へ rollout を `n_rollouts` 本足し、
+    /// マスごとの推定 (1 本 = 1 標本なので stderr がそのまま信頼幅) を平均の降順で返す。
+    /// 本数は空きマスに巡回で配るので、小さな `n_rollouts` を何度呼んでも偏らない。
+    /// 推定は `params.rollout_policy` の rollout だけで、`values_after_card` より粗い。札が無ければ空
+    pub fn step(&mut self, n_rollouts: usize) -> Vec<MoveStat> {
+        let Some(card) = self.card else { return Vec::new() };
+        let Ok(mut st) = self.state.after_draw(card) else { return Vec::new() };
+        let empties: Vec<usize> = st.empty_positions().collect();
+        let keys: Vec<u64> = empties.iter().map(|&pos| {
+            st.place(pos, card);
+            let k = st.key();
+            st.remove(pos);
+            k
+        }).collect();
+        for _ in 0..n_rollouts {
+            let i = self.cursor % empties.len();
+            self.cursor = i + 1;
+            st.place(empties[i], card);
+            let v = self.params.objective.utility(playout(&st, self.params.rollout_policy, &mut self.rng));
+            st.remove(empties[i]);
+            self.rollouts.entry(keys[i]).or_default().push(v);
+        }
+        let mut moves: Vec<MoveStat> = empties.iter().zip(&keys).map(|(&pos, k)| {
+            let s = self.rollouts.get(k).copied().unwrap_or_default();
+            MoveStat { pos, mean: s.mean, stderr: s.stderr(), samples: s.n, gap: 0.0 }
+        }).collect();
+        sort_moves(&mut moves);
+        moves
+    }
+
+    /// 現局面 (ドロー前) の推定値に 1 バッチ足して返す
+    pub fn value(&mut self, budget: Option<Duration>) -> Estimate {
+        let deadline = budget.map(Deadline::after);
+        let mut st = self.state.clone();
+        self.refine(&mut st, deadline)
+    }
+
+    /// `card` を引いたとして各空きマスの推定値に 1 バッチずつ足し、平均の降順で返す
+    /// (`recommend` と同じ形。厳密解のマスは stderr = 0)
+    pub fn values_after_card(&mut self, card: u8, budget: Option<Duration>) -> Result<Vec<MoveStat>, StreamsError> {
+        let mut st = self.state.after_draw(card)?;
+        let deadline = budget.map(Deadline::after);
+        let empties: Vec<usize> = st.empty_positions().collect();
+        let mut moves = Vec::with_capacity(empties.len());
+        for (i, &pos) in empties.iter().enumerate() {
+            // 残り時間を未評価のマスで等分
+            st.place(pos, card);
+            let e = self.refine(&mut st, deadline.map(|d| d.split(empties.len() - i)));
+            st.remove(pos);
+            moves.push(MoveStat { pos, mean: e.mean, stderr: e.stderr, samples: e.samples, gap: 0.0 });
+        }
+        sort_moves(&mut moves);
+        Ok(moves)
+    }
+
+    fn refine(&mut self, st: &mut GameState, deadline: Option<Deadline>) -> Estimate {
+        let p = McParams { deadline, ..self.params };
+        if let Some(mean) = endgame_exact(st, &p, &mut self.tt) {
+            return Estimate { mean, stderr: 0.0, samples: 1, exact: true };
+        }
+        // 期限内に解けなかった終盤も Monte-Carlo で
+        let p = McParams { exact_threshold: 0, ..p };
+        let v = evaluate(st, &p, &mut SimpleRng::new(self.rng.next_u64()));
+        let s = self.cache.entry(st.key()).or_default();
+        if s.n > 0 { self.reused += 1; }
+        s.push(v);
+        Estimate { mean: s.mean, stderr: s.stderr(), samples: s.n, exact: false }
+    }
+}
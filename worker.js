@@ -0,0 +1,15 @@
+// Solver Worker: Wasm を読み込み、メインスレッドからの {id, fn, args} を実行して結果を返す
+import init, * as wasm from "./pkg/streams_solver.js";
+
+const ready = init();
+
+self.onmessage = async ({ data: { id, fn, args } }) => {
+  await ready;
+  try {
+    self.postMessage({ id, ok: wasm[fn](...args) });
+  } catch (e) {
+    // js_sys::Error は clone できないので中身だけ送る
+    const { message, kind, pos, ch, card, expected, found, player } = e;
+    self.postMessage({ id, err: { message, kind, pos, ch, card, expected, found, player } });
+  }
+};